@@ -6,7 +6,6 @@ use image::RgbImage;
 use once_cell::sync::Lazy;
 use opencv::prelude::VideoCaptureTraitConst;
 use opencv::videoio::{self, VideoCaptureTrait, CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH};
-use tokio::sync::mpsc::Sender;
 
 use crate::capture_raw::capture_with_opencv;
 use crate::{
@@ -41,7 +40,7 @@ pub fn open_directshow_device(
     device.set(CAP_PROP_FRAME_HEIGHT, HEIGHT as f64)?;
     let opened = videoio::VideoCapture::is_opened(&device)?;
     if !opened {
-        panic!("Unable to open default camera!");
+        return Err(anyhow::anyhow!("Unable to open {}", device_name));
     }
     log::info!(
         "camera: {} {}x{}",
@@ -57,8 +56,15 @@ pub fn open_directshow_device(
     Ok(device)
 }
 
+pub fn evict_directshow_device(device_name: &str) {
+    if DEVICE_CACHE.lock().unwrap().remove(device_name).is_some() {
+        log::info!("evicted directshow device: {}", device_name);
+    }
+}
+
 #[derive(Debug)]
 pub struct DirectShowCapture {
+    device_name: String,
     device: Arc<Mutex<videoio::VideoCapture>>,
     last: std::time::Instant,
 }
@@ -70,25 +76,23 @@ impl Capture for DirectShowCapture {
         let device = open_directshow_device(device_name)?;
 
         Ok(Self {
+            device_name: device_name.to_owned(),
             device,
             last: std::time::Instant::now(),
         })
     }
 
-    async fn capture(&mut self, tx: &Sender<RgbImage>) -> anyhow::Result<()> {
-        let img = match capture_with_opencv(&mut self.device.lock().unwrap()) {
-            Ok(img) => img,
-            Err(e) => {
-                log::error!("capture_with_opencv failed: {}", e);
-                return Ok(());
-            }
-        };
-        tx.send(img).await?;
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+        let img = capture_with_opencv(&mut self.device.lock().unwrap())?;
         self.last = std::time::Instant::now();
-        Ok(())
+        Ok(Some(img))
     }
 
     fn get_last(&self) -> std::time::Instant {
         self.last
     }
+
+    fn evict(&self) {
+        evict_directshow_device(&self.device_name);
+    }
 }
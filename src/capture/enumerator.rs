@@ -0,0 +1,88 @@
+use eframe::epaint::ahash::HashMap;
+
+#[cfg(target_os = "linux")]
+use crate::capture_raw::get_v4l2_device_name_map;
+#[cfg(windows)]
+use crate::capture_raw::{get_directshow_device_name_map, get_msmf_device_name_map};
+
+/// キャプチャデバイスの一覧を取得する
+pub trait DeviceEnumerator {
+    /// デバイスのindexとデバイス名の対応
+    fn device_name_map(&self) -> anyhow::Result<HashMap<usize, String>>;
+
+    /// index順に並べたデバイス名。取得に失敗した場合は空
+    fn device_names(&self) -> Vec<String> {
+        let mut devices = match self.device_name_map() {
+            Ok(map) => map.into_iter().collect::<Vec<(usize, String)>>(),
+            Err(e) => {
+                log::error!("failed to enumerate devices: {}", e);
+                return Vec::new();
+            }
+        };
+        devices.sort_by_key(|(i, _)| *i);
+        devices.into_iter().map(|(_, name)| name).collect()
+    }
+}
+
+#[cfg(windows)]
+pub struct MSMFEnumerator;
+
+#[cfg(windows)]
+impl DeviceEnumerator for MSMFEnumerator {
+    fn device_name_map(&self) -> anyhow::Result<HashMap<usize, String>> {
+        get_msmf_device_name_map()
+    }
+}
+
+#[cfg(windows)]
+pub struct DirectShowEnumerator;
+
+#[cfg(windows)]
+impl DeviceEnumerator for DirectShowEnumerator {
+    fn device_name_map(&self) -> anyhow::Result<HashMap<usize, String>> {
+        get_directshow_device_name_map()
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct V4L2Enumerator;
+
+#[cfg(target_os = "linux")]
+impl DeviceEnumerator for V4L2Enumerator {
+    fn device_name_map(&self) -> anyhow::Result<HashMap<usize, String>> {
+        get_v4l2_device_name_map()
+    }
+}
+
+// キャプチャデバイスに対応していないプラットフォーム用
+#[cfg(not(any(windows, target_os = "linux")))]
+pub struct NoDeviceEnumerator;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+impl DeviceEnumerator for NoDeviceEnumerator {
+    fn device_name_map(&self) -> anyhow::Result<HashMap<usize, String>> {
+        Ok(HashMap::default())
+    }
+}
+
+/// 現在のプラットフォームと設定で使うDeviceEnumeratorを返す
+#[cfg(windows)]
+pub fn device_enumerator(directshow: bool) -> Box<dyn DeviceEnumerator> {
+    if directshow {
+        Box::new(DirectShowEnumerator)
+    } else {
+        Box::new(MSMFEnumerator)
+    }
+}
+
+/// 現在のプラットフォームと設定で使うDeviceEnumeratorを返す
+/// `directshow` はWindowsでのみ意味を持つ
+#[cfg(target_os = "linux")]
+pub fn device_enumerator(_directshow: bool) -> Box<dyn DeviceEnumerator> {
+    Box::new(V4L2Enumerator)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn device_enumerator(_directshow: bool) -> Box<dyn DeviceEnumerator> {
+    Box::new(NoDeviceEnumerator)
+}
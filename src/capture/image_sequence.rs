@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use image::RgbImage;
+use opencv::imgcodecs::{imread, IMREAD_COLOR};
+
+use crate::capture_raw::mat_to_rgb_image;
+
+use super::Capture;
+
+const EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+// 連番画像にはfpsの情報がないので、30fpsで撮ったものとして扱う
+const FRAME_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 30);
+// 終端に達した後、次に読もうとするまで待つ時間
+const FINISHED_WAIT: Duration = Duration::from_secs(1);
+
+fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// ディレクトリならその中の画像ファイルを、それ以外はglobとして解釈して、ソートして返す
+fn list_frame_paths(pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = if Path::new(pattern).is_dir() {
+        std::fs::read_dir(pattern)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect::<Vec<PathBuf>>()
+    } else {
+        glob::glob(pattern)?
+            .filter_map(|p| p.ok())
+            .collect::<Vec<PathBuf>>()
+    };
+    paths.retain(|p| p.is_file() && has_image_extension(p));
+    paths.sort();
+    Ok(paths)
+}
+
+/// ディレクトリ(またはglob)にあるPNG/JPEGの連番画像をフレームとして読み込む
+pub struct ImageSequenceCapture {
+    paths: Vec<PathBuf>,
+    index: usize,
+    last: Instant,
+}
+
+impl ImageSequenceCapture {
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.paths.len()
+    }
+}
+
+#[async_trait]
+impl Capture for ImageSequenceCapture {
+    fn new(pattern: &str) -> anyhow::Result<Self> {
+        log::info!("ImageSequenceCapture::new({})", pattern);
+        let paths = list_frame_paths(pattern)?;
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!("no image files found: {}", pattern));
+        }
+        log::info!("image sequence: {} frames", paths.len());
+
+        Ok(Self {
+            paths,
+            index: 0,
+            last: Instant::now(),
+        })
+    }
+
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+        if self.is_finished() {
+            return Ok(None);
+        }
+        let path = &self.paths[self.index];
+        self.index += 1;
+        // 読めなかった画像も1フレームとして数え、次のフレームまで待つ
+        self.last = Instant::now();
+        if self.is_finished() {
+            log::info!("reached the end of the image sequence");
+        }
+
+        let img = {
+            let frame = imread(&path.to_string_lossy(), IMREAD_COLOR)?;
+            match mat_to_rgb_image(&frame) {
+                Ok(img) => img,
+                Err(e) => {
+                    log::error!("failed to read {}: {}", path.display(), e);
+                    return Ok(None);
+                }
+            }
+        };
+        Ok(Some(img))
+    }
+
+    fn get_last(&self) -> Instant {
+        self.last
+    }
+
+    fn stream_time(&self) -> Option<Duration> {
+        let index = self.index.checked_sub(1)?;
+        Some(FRAME_INTERVAL * index as u32)
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    async fn sleep(&self, frame_rate: f64) {
+        // 終端に達したら読むものはないので、ループが空回りしないように待つ
+        if self.is_finished() {
+            tokio::time::sleep(FINISHED_WAIT).await;
+            return;
+        }
+        let duration = Duration::from_secs_f64(1.0 / frame_rate);
+        let elapsed = self.last.elapsed();
+        if elapsed < duration {
+            tokio::time::sleep(duration - elapsed).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_frame_paths() -> anyhow::Result<()> {
+        // 並列に動くほかのテストのプロセスと同じディレクトリを使わないようにする
+        let dir = std::env::temp_dir().join(format!(
+            "lounge-memo-test-image-sequence-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        for name in ["race_02.png", "race_01.png", "total_01.JPG", "memo.txt"] {
+            std::fs::write(dir.join(name), [])?;
+        }
+
+        let paths = list_frame_paths(&dir.to_string_lossy())?;
+        let names = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["race_01.png", "race_02.png", "total_01.JPG"]);
+
+        let paths = list_frame_paths(&dir.join("race_*.png").to_string_lossy())?;
+        assert_eq!(paths.len(), 2);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,15 +1,32 @@
 use async_trait::async_trait;
 use image::RgbImage;
-use tokio::sync::mpsc::Sender;
 
+use crate::settings::{CaptureSource, Settings};
+
+#[cfg(windows)]
 mod directshow;
+mod enumerator;
+mod image_sequence;
+#[cfg(windows)]
 mod msmf;
+mod network;
+mod supervisor;
+#[cfg(target_os = "linux")]
+mod v4l2;
+mod video_file;
 
+#[cfg(windows)]
 pub use directshow::DirectShowCapture;
+pub use image_sequence::ImageSequenceCapture;
+#[cfg(windows)]
 pub use msmf::MSMFCapture;
+pub use network::NetworkCapture;
+#[cfg(target_os = "linux")]
+pub use v4l2::V4L2Capture;
+pub use video_file::VideoFileCapture;
 
-pub use directshow::open_directshow_device;
-pub use msmf::open_msmf_device;
+pub use enumerator::{device_enumerator, DeviceEnumerator};
+pub use supervisor::{CaptureStatus, CaptureSupervisor};
 
 #[async_trait]
 pub trait Capture: Send + Sync {
@@ -17,16 +34,66 @@ pub trait Capture: Send + Sync {
     where
         Self: Sized;
 
-    async fn capture(&mut self, tx: &Sender<RgbImage>) -> anyhow::Result<()>;
+    // フレームを1枚取得する。動画の終端などで返すフレームがない場合はNone
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>>;
 
     fn get_last(&self) -> std::time::Instant;
 
-    // 30fpsで駆動
-    async fn sleep(&self) {
+    // 動画ファイルなど、ソース自体が再生位置を持っている場合は直前に返したフレームの再生位置
+    // 実時間のソースはNoneで、Supervisorが開始からの経過時間を使う
+    fn stream_time(&self) -> Option<std::time::Duration> {
+        None
+    }
+
+    // frame_rateで駆動
+    async fn sleep(&self, frame_rate: f64) {
         let now = std::time::Instant::now();
-        let duration = std::time::Duration::from_secs_f64(1.0 / 30.0);
+        let duration = std::time::Duration::from_secs_f64(1.0 / frame_rate);
         if now < self.get_last() + duration {
             tokio::time::sleep(duration - (now - self.get_last())).await;
         }
     }
+
+    // キャプチャボードなど、実時間で映像が流れてくるソースかどうか
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    // デバイスのキャッシュを破棄して、次に開くときに開き直すようにする
+    fn evict(&self) {}
+}
+
+#[cfg(windows)]
+fn open_device_capture(settings: &Settings) -> anyhow::Result<Box<dyn Capture>> {
+    if settings.directshow() {
+        Ok(Box::new(DirectShowCapture::new(settings.device_name())?))
+    } else {
+        Ok(Box::new(MSMFCapture::new(settings.device_name())?))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_device_capture(settings: &Settings) -> anyhow::Result<Box<dyn Capture>> {
+    Ok(Box::new(V4L2Capture::new(settings.device_name())?))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn open_device_capture(_settings: &Settings) -> anyhow::Result<Box<dyn Capture>> {
+    Err(anyhow::anyhow!(
+        "capture devices are not supported on this platform"
+    ))
+}
+
+pub fn open_capture(settings: &Settings) -> anyhow::Result<Box<dyn Capture>> {
+    match settings.source() {
+        CaptureSource::Device => open_device_capture(settings),
+        CaptureSource::VideoFile => Ok(Box::new(VideoFileCapture::with_speed(
+            settings.source_path(),
+            settings.playback_speed(),
+        )?)),
+        CaptureSource::ImageSequence => {
+            Ok(Box::new(ImageSequenceCapture::new(settings.source_path())?))
+        }
+        CaptureSource::Network => Ok(Box::new(NetworkCapture::new(settings.source_path())?)),
+    }
 }
@@ -7,7 +7,6 @@ use async_trait::async_trait;
 use escapi::Device;
 use image::RgbImage;
 use once_cell::sync::Lazy;
-use tokio::sync::mpsc::Sender;
 
 use crate::{
     capture_raw::{capture_with_escapi, get_msmf_device_name_map},
@@ -45,7 +44,14 @@ pub fn open_msmf_device(device_name: &str) -> anyhow::Result<Arc<Mutex<Device>>>
     Ok(device)
 }
 
+pub fn evict_msmf_device(device_name: &str) {
+    if DEVICE_CACHE.lock().unwrap().remove(device_name).is_some() {
+        log::info!("evicted msmf device: {}", device_name);
+    }
+}
+
 pub struct MSMFCapture {
+    device_name: String,
     device: Arc<Mutex<escapi::Device>>,
     last: std::time::Instant,
 }
@@ -57,19 +63,23 @@ impl Capture for MSMFCapture {
         let device = open_msmf_device(device_name)?;
 
         Ok(Self {
+            device_name: device_name.to_owned(),
             device,
             last: std::time::Instant::now(),
         })
     }
 
-    async fn capture(&mut self, tx: &Sender<RgbImage>) -> anyhow::Result<()> {
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
         let img = capture_with_escapi(&self.device.lock().unwrap())?;
-        tx.send(img).await?;
         self.last = std::time::Instant::now();
-        Ok(())
+        Ok(Some(img))
     }
 
     fn get_last(&self) -> std::time::Instant {
         self.last
     }
+
+    fn evict(&self) {
+        evict_msmf_device(&self.device_name);
+    }
 }
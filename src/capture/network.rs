@@ -0,0 +1,391 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use image::{imageops::FilterType, ImageBuffer, ImageFormat, RgbImage};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::size::{HEIGHT, WIDTH};
+
+use super::Capture;
+
+// 切断されてから再接続を試みるまでの間隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// この時間フレームが届かなければ切断されたとみなす
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// 1フレームとして受け付ける最大のサイズ
+const MAX_FRAME_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamKind {
+    // multipart/x-mixed-replace で送られてくるJPEG
+    Mjpeg,
+    // 幅(u32 BE)、高さ(u32 BE)に続けて幅x高さx3バイトのRGB24が並ぶ
+    RawRgb,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StreamAddress {
+    kind: StreamKind,
+    host: String,
+    path: String,
+}
+
+fn parse_address(url: &str) -> anyhow::Result<StreamAddress> {
+    let (kind, rest) = if let Some(rest) = url.strip_prefix("http://") {
+        (StreamKind::Mjpeg, rest)
+    } else if let Some(rest) = url.strip_prefix("tcp://") {
+        (StreamKind::RawRgb, rest)
+    } else {
+        return Err(anyhow::anyhow!(
+            "unsupported stream url (expected http:// or tcp://): {}",
+            url
+        ));
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(anyhow::anyhow!("host is empty: {}", url));
+    }
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok(StreamAddress {
+        kind,
+        host,
+        path: path.to_string(),
+    })
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// JPEGのSOI(FFD8)からEOI(FFD9)までの範囲を探す
+fn find_jpeg(buffer: &[u8]) -> Option<(usize, usize)> {
+    let start = find_subslice(buffer, &[0xFF, 0xD8])?;
+    let end = find_subslice(&buffer[start + 2..], &[0xFF, 0xD9])? + start + 4;
+    Some((start, end))
+}
+
+fn resize_to_capture_size(img: RgbImage) -> RgbImage {
+    if img.width() as usize == WIDTH && img.height() as usize == HEIGHT {
+        return img;
+    }
+    image::imageops::resize(&img, WIDTH as _, HEIGHT as _, FilterType::Triangle)
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    async fn open(address: &StreamAddress) -> anyhow::Result<Connection> {
+        let mut stream = TcpStream::connect(&address.host).await?;
+        let mut buffer = Vec::new();
+        if address.kind == StreamKind::Mjpeg {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                address.path, address.host
+            );
+            stream.write_all(request.as_bytes()).await?;
+            // レスポンスヘッダを読み飛ばす。残りはフレームとして使う
+            let header_end = loop {
+                if let Some(i) = find_subslice(&buffer, b"\r\n\r\n") {
+                    break i + 4;
+                }
+                if buffer.len() > 64 * 1024 {
+                    return Err(anyhow::anyhow!("response header is too large"));
+                }
+                let mut chunk = [0u8; 4096];
+                let n = stream.read(&mut chunk).await?;
+                if n == 0 {
+                    return Err(anyhow::anyhow!("connection closed before response header"));
+                }
+                buffer.extend_from_slice(&chunk[..n]);
+            };
+            let header = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+            let status_line = header.lines().next().unwrap_or_default();
+            if status_line.split_whitespace().nth(1) != Some("200") {
+                return Err(anyhow::anyhow!("unexpected response: {}", status_line));
+            }
+            buffer.drain(..header_end);
+        }
+        Ok(Connection { stream, buffer })
+    }
+
+    async fn read_frame(&mut self, kind: StreamKind) -> anyhow::Result<RgbImage> {
+        match kind {
+            StreamKind::Mjpeg => self.read_jpeg_frame().await,
+            StreamKind::RawRgb => self.read_raw_frame().await,
+        }
+    }
+
+    async fn read_jpeg_frame(&mut self) -> anyhow::Result<RgbImage> {
+        loop {
+            if let Some((start, end)) = find_jpeg(&self.buffer) {
+                let img = image::load_from_memory_with_format(
+                    &self.buffer[start..end],
+                    ImageFormat::Jpeg,
+                );
+                self.buffer.drain(..end);
+                return Ok(resize_to_capture_size(img?.to_rgb8()));
+            }
+            if self.buffer.len() > MAX_FRAME_BYTES {
+                return Err(anyhow::anyhow!("no jpeg frame found in the stream"));
+            }
+            let mut chunk = vec![0u8; 64 * 1024];
+            let n = self.stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(anyhow::anyhow!("connection closed"));
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    async fn read_raw_frame(&mut self) -> anyhow::Result<RgbImage> {
+        let width = self.stream.read_u32().await? as usize;
+        let height = self.stream.read_u32().await? as usize;
+        // 壊れたヘッダで掛け算があふれないようにする
+        let length = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .filter(|n| *n != 0 && *n <= MAX_FRAME_BYTES)
+            .ok_or_else(|| anyhow::anyhow!("invalid frame size: {}x{}", width, height))?;
+        let mut buffer = vec![0u8; length];
+        self.stream.read_exact(&mut buffer).await?;
+        let img = ImageBuffer::from_raw(width as _, height as _, buffer)
+            .ok_or(anyhow::anyhow!("invalid frame buffer"))?;
+        Ok(resize_to_capture_size(img))
+    }
+}
+
+/// 別のマシンから送られてくるストリームをキャプチャデバイスの代わりに読み込む
+/// - `http://host:port/path`: MJPEG over HTTP
+/// - `tcp://host:port`: 幅と高さ(u32 BE)を先頭につけたRGB24のフレーム列
+///
+/// ストリームが切れた場合は、次のcapture呼び出しで再接続を試みる
+pub struct NetworkCapture {
+    address: StreamAddress,
+    connection: Option<Connection>,
+    last_connect_attempt: Option<Instant>,
+    last: Instant,
+}
+
+#[async_trait]
+impl Capture for NetworkCapture {
+    fn new(url: &str) -> anyhow::Result<Self> {
+        log::info!("NetworkCapture::new({})", url);
+        let address = parse_address(url)?;
+
+        Ok(Self {
+            address,
+            connection: None,
+            last_connect_attempt: None,
+            last: Instant::now(),
+        })
+    }
+
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+        // 失敗した場合もフレームを読んだことにして、次の呼び出しまでsleepで待たせる
+        self.last = Instant::now();
+        if self.connection.is_none() {
+            if let Some(at) = self.last_connect_attempt {
+                if at.elapsed() < RECONNECT_INTERVAL {
+                    return Err(anyhow::anyhow!(
+                        "waiting to reconnect to {}",
+                        self.address.host
+                    ));
+                }
+            }
+            self.last_connect_attempt = Some(Instant::now());
+            // 接続できても応答がない場合に、ここで止まり続けないようにする
+            let connection = tokio::time::timeout(READ_TIMEOUT, Connection::open(&self.address))
+                .await
+                .map_err(|_| anyhow::anyhow!("connecting to {} timed out", self.address.host))?
+                .map_err(|e| {
+                    anyhow::anyhow!("failed to connect to {}: {}", self.address.host, e)
+                })?;
+            log::info!("connected to {}", self.address.host);
+            self.connection = Some(connection);
+        }
+
+        let connection = self.connection.as_mut().unwrap();
+        let result =
+            tokio::time::timeout(READ_TIMEOUT, connection.read_frame(self.address.kind)).await;
+        let img = match result {
+            Ok(Ok(img)) => img,
+            Ok(Err(e)) => {
+                self.connection = None;
+                return Err(anyhow::anyhow!(
+                    "stream from {} dropped: {}",
+                    self.address.host,
+                    e
+                ));
+            }
+            Err(_) => {
+                self.connection = None;
+                return Err(anyhow::anyhow!(
+                    "stream from {} timed out",
+                    self.address.host
+                ));
+            }
+        };
+        self.last = Instant::now();
+        Ok(Some(img))
+    }
+
+    fn get_last(&self) -> Instant {
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn test_image() -> RgbImage {
+        ImageBuffer::from_fn(16, 9, |x, y| {
+            image::Rgb([(x * 10) as u8, (y * 20) as u8, 128])
+        })
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("http://192.168.0.10:8080/stream.mjpg").unwrap(),
+            StreamAddress {
+                kind: StreamKind::Mjpeg,
+                host: "192.168.0.10:8080".to_string(),
+                path: "/stream.mjpg".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_address("tcp://localhost:9000").unwrap(),
+            StreamAddress {
+                kind: StreamKind::RawRgb,
+                host: "localhost:9000".to_string(),
+                path: "/".to_string(),
+            }
+        );
+        assert!(parse_address("rtsp://localhost:554").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_raw_rgb_stream_reconnects() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            // 1フレーム送るごとに切断する
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let img = test_image();
+                socket.write_u32(img.width()).await.unwrap();
+                socket.write_u32(img.height()).await.unwrap();
+                socket.write_all(img.as_raw()).await.unwrap();
+            }
+        });
+
+        let mut capture = NetworkCapture::new(&format!("tcp://127.0.0.1:{}", port))?;
+        let mut received = 0;
+        let mut errors = 0;
+        let started = Instant::now();
+        while received < 2 && started.elapsed() < Duration::from_secs(10) {
+            match capture.capture().await {
+                Ok(Some(img)) => {
+                    assert_eq!(img.dimensions(), (WIDTH as u32, HEIGHT as u32));
+                    received += 1;
+                }
+                Ok(None) => {}
+                // 切断はエラーとして返し、Supervisorに任せる
+                Err(_) => errors += 1,
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(received, 2);
+        assert!(errors > 0);
+        server.await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_raw_rgb_invalid_size() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_u32(u32::MAX).await.unwrap();
+            socket.write_u32(u32::MAX).await.unwrap();
+        });
+
+        // 大きすぎるフレームは読まずにエラーにする
+        let mut capture = NetworkCapture::new(&format!("tcp://127.0.0.1:{}", port))?;
+        assert!(capture.capture().await.is_err());
+        server.await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mjpeg_no_response() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            // 接続を受け付けるだけで、何も返さない
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(READ_TIMEOUT * 2).await;
+            drop(socket);
+        });
+
+        let mut capture = NetworkCapture::new(&format!("http://127.0.0.1:{}/stream", port))?;
+        let started = Instant::now();
+        assert!(capture.capture().await.is_err());
+        assert!(started.elapsed() < READ_TIMEOUT * 2);
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mjpeg_stream() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let mut jpeg = Vec::new();
+            test_image()
+                .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+                .unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\r\n")
+                .await
+                .unwrap();
+            for _ in 0..2 {
+                let part = format!(
+                    "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                    jpeg.len()
+                );
+                socket.write_all(part.as_bytes()).await.unwrap();
+                socket.write_all(&jpeg).await.unwrap();
+                socket.write_all(b"\r\n").await.unwrap();
+            }
+        });
+
+        let mut capture = NetworkCapture::new(&format!("http://127.0.0.1:{}/stream", port))?;
+        for _ in 0..2 {
+            let img = capture.capture().await?.unwrap();
+            assert_eq!(img.dimensions(), (WIDTH as u32, HEIGHT as u32));
+        }
+        server.await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,427 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use image::RgbImage;
+
+use crate::clock::{Clock, SystemClock};
+use crate::frame::Frame;
+use crate::settings::{CaptureSource, Settings};
+
+use super::{open_capture, Capture};
+
+// この時間キャプチャに失敗し続けたら開き直す
+const ERROR_TIMEOUT: Duration = Duration::from_secs(5);
+// 真っ黒なフレームがこの時間続いたら開き直す
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+// 同じフレームがこの時間続いたらフリーズしているとみなす
+const FROZEN_THRESHOLD: Duration = Duration::from_secs(2);
+// 真っ黒なフレームがこの時間続いたら信号がないとみなす
+// ロード画面などで一瞬暗転するのは無視したい
+const NO_SIGNAL_THRESHOLD: Duration = Duration::from_secs(3);
+// これ未満の輝度しかないフレームを真っ黒とみなす
+const BLACK_THRESHOLD: u8 = 16;
+// 同じフレームかどうかを調べるときに見る画素の数(縦横それぞれ)
+const HASH_GRID: u32 = 64;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureStatus {
+    // キャプチャを開けていない
+    Disconnected,
+    Healthy,
+    // 同じフレームが続いている
+    Frozen,
+    // 真っ黒なフレームが続いている
+    NoSignal,
+    // 失敗したので開き直すのを待っている
+    Reconnecting,
+}
+
+impl Display for CaptureStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureStatus::Disconnected => write!(f, "未接続"),
+            CaptureStatus::Healthy => write!(f, "正常"),
+            CaptureStatus::Frozen => write!(f, "映像が止まっています"),
+            CaptureStatus::NoSignal => write!(f, "信号がありません"),
+            CaptureStatus::Reconnecting => write!(f, "再接続中"),
+        }
+    }
+}
+
+// フレームの出どころを表す文字列
+fn source_id(settings: &Settings) -> String {
+    match settings.source() {
+        CaptureSource::Device => format!("device:{}", settings.device_name()),
+        CaptureSource::VideoFile => format!("video:{}", settings.source_path()),
+        CaptureSource::ImageSequence => format!("images:{}", settings.source_path()),
+        CaptureSource::Network => format!("network:{}", settings.source_path()),
+    }
+}
+
+fn is_black_frame(img: &RgbImage) -> bool {
+    img.as_raw().iter().all(|b| *b < BLACK_THRESHOLD)
+}
+
+// 格子状に間引いた画素のハッシュ。フレームをまるごと覚えずに、同じフレームかどうかを調べる
+fn frame_hash(img: &RgbImage) -> u64 {
+    let (width, height) = img.dimensions();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    if width == 0 || height == 0 {
+        return hash;
+    }
+    for y in (0..HASH_GRID).map(|i| i * height / HASH_GRID) {
+        for x in (0..HASH_GRID).map(|i| i * width / HASH_GRID) {
+            for byte in img.get_pixel(x, y).0 {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+    hash
+}
+
+// フレームの中身から、フリーズや信号なしを判定する
+#[derive(Debug, Default)]
+struct HealthMonitor {
+    previous: Option<u64>,
+    frozen_since: Option<Instant>,
+    black_since: Option<Instant>,
+}
+
+impl HealthMonitor {
+    fn observe(&mut self, img: &RgbImage, now: Instant) -> CaptureStatus {
+        if is_black_frame(img) {
+            self.black_since.get_or_insert(now);
+        } else {
+            self.black_since = None;
+        }
+        // 真っ黒なフレーム同士は同じなので、フリーズとしては扱わない
+        let hash = frame_hash(img);
+        if self.black_since.is_none() && self.previous == Some(hash) {
+            self.frozen_since.get_or_insert(now);
+        } else {
+            self.frozen_since = None;
+        }
+        self.previous = Some(hash);
+
+        match (self.black_since, self.frozen_since) {
+            (Some(since), _) if now - since >= NO_SIGNAL_THRESHOLD => CaptureStatus::NoSignal,
+            (_, Some(since)) if now - since >= FROZEN_THRESHOLD => CaptureStatus::Frozen,
+            _ => CaptureStatus::Healthy,
+        }
+    }
+
+    // メニュー画面などは止まっていても正常なので、同じフレームが続くだけでは開き直さない
+    fn is_stalled(&self, now: Instant) -> bool {
+        self.black_since
+            .is_some_and(|since| now - since >= STALL_TIMEOUT)
+    }
+}
+
+/// Captureを監視して、失敗や映像の停止を検出したら開き直す
+pub struct CaptureSupervisor {
+    settings: Settings,
+    capture: Option<Box<dyn Capture>>,
+    status: CaptureStatus,
+    monitor: HealthMonitor,
+    consecutive_errors: u32,
+    // 失敗し始めた時刻。成功したらNone
+    failing_since: Option<Instant>,
+    retry_at: Instant,
+    backoff: Duration,
+    // 実時間のソースのタイムスタンプを測る時計。開き直しても変えない
+    clock: Box<dyn Clock>,
+    sequence: u64,
+    source_id: Arc<str>,
+}
+
+impl CaptureSupervisor {
+    pub fn new(settings: Settings) -> Self {
+        let mut supervisor = Self::with_clock(settings, Box::new(SystemClock::new()));
+        supervisor.open();
+        supervisor
+    }
+
+    // 開く前の状態で作る
+    fn with_clock(settings: Settings, clock: Box<dyn Clock>) -> Self {
+        let source_id = source_id(&settings).into();
+        Self {
+            settings,
+            capture: None,
+            status: CaptureStatus::Disconnected,
+            monitor: HealthMonitor::default(),
+            consecutive_errors: 0,
+            failing_since: None,
+            retry_at: Instant::now(),
+            backoff: INITIAL_BACKOFF,
+            clock,
+            sequence: 0,
+            source_id,
+        }
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        self.status
+    }
+
+    pub fn is_opened(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.capture.as_ref().map(|c| c.is_live()).unwrap_or(true)
+    }
+
+    fn open(&mut self) {
+        match open_capture(&self.settings) {
+            Ok(capture) => {
+                self.capture = Some(capture);
+                self.status = CaptureStatus::Healthy;
+                self.monitor = HealthMonitor::default();
+                self.consecutive_errors = 0;
+                self.failing_since = None;
+            }
+            Err(e) => {
+                log::error!("failed to open capture: {:?}", e);
+                self.schedule_retry(CaptureStatus::Disconnected);
+            }
+        }
+    }
+
+    fn schedule_retry(&mut self, status: CaptureStatus) {
+        self.status = status;
+        self.retry_at = Instant::now() + self.backoff;
+        log::info!("retry opening capture in {:?}", self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    fn reconnect(&mut self) {
+        if let Some(capture) = self.capture.take() {
+            capture.evict();
+        }
+        self.schedule_retry(CaptureStatus::Reconnecting);
+    }
+
+    pub async fn capture(&mut self) -> Option<Frame> {
+        if self.capture.is_none() {
+            if Instant::now() < self.retry_at {
+                return None;
+            }
+            self.open();
+        }
+        let capture = self.capture.as_mut()?;
+
+        let img = match capture.capture().await {
+            Ok(img) => img,
+            Err(e) => {
+                self.consecutive_errors += 1;
+                if self.consecutive_errors == 1 {
+                    log::warn!("capture failed: {:?}", e);
+                }
+                // 一時的な失敗では開き直さず、失敗が続いた時間で判断する
+                let since = *self.failing_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= ERROR_TIMEOUT {
+                    if capture.is_live() {
+                        log::error!(
+                            "capture failed {} times in {:?}, reconnecting",
+                            self.consecutive_errors,
+                            since.elapsed()
+                        );
+                        self.reconnect();
+                    } else {
+                        // 動画ファイルなどは開き直すと最初から読み直してしまうので、読めない部分を飛ばして進める
+                        log::error!(
+                            "capture failed {} times in {:?}, skipping",
+                            self.consecutive_errors,
+                            since.elapsed()
+                        );
+                        self.failing_since = Some(Instant::now());
+                    }
+                }
+                return None;
+            }
+        };
+        self.consecutive_errors = 0;
+        self.failing_since = None;
+        let img = img?;
+
+        let now = Instant::now();
+        let timestamp = match capture.stream_time() {
+            Some(t) => t,
+            None => self.clock.now(),
+        };
+        let status = self.monitor.observe(&img, now);
+        if status != self.status {
+            log::info!("capture status: {:?} -> {:?}", self.status, status);
+            self.status = status;
+        }
+        if status == CaptureStatus::Healthy {
+            self.backoff = INITIAL_BACKOFF;
+        }
+        // 録画ファイルは開き直すと最初からになってしまうので、実時間のソースだけ開き直す
+        if capture.is_live() && self.monitor.is_stalled(now) {
+            log::error!("capture seems to be stalled, reconnecting");
+            self.reconnect();
+        }
+        let frame = Frame::new(img, timestamp, self.sequence, self.source_id.clone());
+        self.sequence += 1;
+        Some(frame)
+    }
+
+    pub async fn sleep(&self, frame_rate: f64) {
+        match self.capture.as_ref() {
+            // 失敗したときはフレームを読んだ時刻が進まないので、Captureに任せると待たずに次を呼んでしまう
+            Some(_) if self.failing_since.is_some() => {
+                tokio::time::sleep(Duration::from_secs_f64(1.0 / frame_rate)).await;
+            }
+            Some(capture) => capture.sleep(frame_rate).await,
+            None => {
+                let now = Instant::now();
+                let wait = if self.retry_at > now {
+                    self.retry_at - now
+                } else {
+                    Duration::from_secs_f64(1.0 / frame_rate)
+                };
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use image::{ImageBuffer, Rgb};
+
+    use crate::clock::MockClock;
+
+    use super::*;
+
+    // 呼ばれるたびに少しずつ明るさの違うフレームを返す、実時間のソースの代わり
+    struct StubCapture {
+        count: u8,
+    }
+
+    #[async_trait]
+    impl Capture for StubCapture {
+        fn new(_device_name: &str) -> anyhow::Result<Self> {
+            Ok(StubCapture { count: 0 })
+        }
+
+        async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+            self.count = self.count.wrapping_add(1);
+            Ok(Some(filled(100 + self.count)))
+        }
+
+        fn get_last(&self) -> Instant {
+            Instant::now()
+        }
+    }
+
+    // 読むたびに失敗する、動画ファイルの代わり
+    struct BrokenFileCapture;
+
+    #[async_trait]
+    impl Capture for BrokenFileCapture {
+        fn new(_device_name: &str) -> anyhow::Result<Self> {
+            Ok(BrokenFileCapture)
+        }
+
+        async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+            Err(anyhow::anyhow!("broken frame"))
+        }
+
+        fn get_last(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn is_live(&self) -> bool {
+            false
+        }
+    }
+
+    fn filled(value: u8) -> RgbImage {
+        ImageBuffer::from_pixel(8, 8, Rgb([value, value, value]))
+    }
+
+    #[test]
+    fn test_health_monitor() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::default();
+        assert_eq!(monitor.observe(&filled(128), start), CaptureStatus::Healthy);
+        // 同じフレームが続いてもすぐにはフリーズ扱いにしない
+        assert_eq!(
+            monitor.observe(&filled(128), start + Duration::from_secs(1)),
+            CaptureStatus::Healthy
+        );
+        assert_eq!(
+            monitor.observe(
+                &filled(128),
+                start + FROZEN_THRESHOLD + Duration::from_secs(1)
+            ),
+            CaptureStatus::Frozen
+        );
+        // メニュー画面のように止まっているだけなら開き直さない
+        assert!(!monitor.is_stalled(start + STALL_TIMEOUT + Duration::from_secs(1)));
+
+        // 違うフレームが来たら正常に戻る
+        let now = start + Duration::from_secs(20);
+        assert_eq!(monitor.observe(&filled(100), now), CaptureStatus::Healthy);
+        assert!(!monitor.is_stalled(now));
+
+        assert_eq!(monitor.observe(&filled(0), now), CaptureStatus::Healthy);
+        assert_eq!(
+            monitor.observe(&filled(0), now + NO_SIGNAL_THRESHOLD),
+            CaptureStatus::NoSignal
+        );
+        assert!(monitor.is_stalled(now + STALL_TIMEOUT));
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let mut img = filled(128);
+        let hash = frame_hash(&img);
+        assert_eq!(frame_hash(&filled(128)), hash);
+        img.put_pixel(0, 0, Rgb([0, 0, 0]));
+        assert_ne!(frame_hash(&img), hash);
+        assert_eq!(
+            frame_hash(&RgbImage::new(0, 0)),
+            frame_hash(&RgbImage::new(0, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors_do_not_reopen_file() -> anyhow::Result<()> {
+        let settings = Settings::new("".to_string(), false, "INFO".to_string(), false);
+        let mut supervisor =
+            CaptureSupervisor::with_clock(settings, Box::new(MockClock::default()));
+        supervisor.capture = Some(Box::new(BrokenFileCapture::new("")?));
+        supervisor.failing_since = Some(Instant::now() - ERROR_TIMEOUT * 2);
+
+        // 開き直すと最初から読み直してしまうので、失敗が続いても開いたままにする
+        assert!(supervisor.capture().await.is_none());
+        assert!(supervisor.capture.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_live_timestamp() -> anyhow::Result<()> {
+        let clock = MockClock::default();
+        let settings = Settings::new("".to_string(), false, "INFO".to_string(), false);
+        let mut supervisor = CaptureSupervisor::with_clock(settings, Box::new(clock.clone()));
+        supervisor.capture = Some(Box::new(StubCapture::new("")?));
+
+        // 実時間のソースは、時計の時間がタイムスタンプになる
+        clock.advance(Duration::from_secs(5));
+        let frame = supervisor.capture().await.unwrap();
+        assert_eq!(frame.timestamp(), Duration::from_secs(5));
+        clock.advance(Duration::from_millis(100));
+        let frame = supervisor.capture().await.unwrap();
+        assert_eq!(frame.timestamp(), Duration::from_millis(5100));
+        assert_eq!(frame.sequence(), 1);
+        Ok(())
+    }
+}
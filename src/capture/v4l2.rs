@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use image::RgbImage;
+use once_cell::sync::Lazy;
+use opencv::prelude::VideoCaptureTraitConst;
+use opencv::videoio::{self, VideoCaptureTrait, CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH};
+
+use crate::capture_raw::capture_with_opencv;
+use crate::{
+    capture_raw::get_v4l2_device_name_map,
+    size::{HEIGHT, WIDTH},
+};
+
+use super::Capture;
+
+type DeviceCache = HashMap<String, Arc<Mutex<videoio::VideoCapture>>>;
+
+static DEVICE_CACHE: Lazy<Arc<Mutex<DeviceCache>>> = Lazy::new(|| {
+    let map = HashMap::new();
+    Arc::new(Mutex::new(map))
+});
+
+pub fn open_v4l2_device(device_name: &str) -> anyhow::Result<Arc<Mutex<videoio::VideoCapture>>> {
+    if let Some(device) = DEVICE_CACHE.lock().unwrap().get(device_name) {
+        return Ok(device.clone());
+    }
+
+    let device_name_map = get_v4l2_device_name_map()?;
+    let device_index = device_name_map
+        .into_iter()
+        .find(|(_, v)| v == device_name)
+        .map(|(k, _)| k)
+        .ok_or(anyhow::anyhow!("device_name not found"))?;
+    let mut device = videoio::VideoCapture::new(device_index as _, videoio::CAP_V4L2)?;
+    device.set(CAP_PROP_FRAME_WIDTH, WIDTH as f64)?;
+    device.set(CAP_PROP_FRAME_HEIGHT, HEIGHT as f64)?;
+    let opened = videoio::VideoCapture::is_opened(&device)?;
+    if !opened {
+        return Err(anyhow::anyhow!("Unable to open /dev/video{}", device_index));
+    }
+    log::info!(
+        "camera: {} {}x{}",
+        device_name,
+        device.get(CAP_PROP_FRAME_WIDTH)?,
+        device.get(CAP_PROP_FRAME_HEIGHT)?,
+    );
+    let device = Arc::new(Mutex::new(device));
+    DEVICE_CACHE
+        .lock()
+        .unwrap()
+        .insert(device_name.to_owned(), device.clone());
+    Ok(device)
+}
+
+pub fn evict_v4l2_device(device_name: &str) {
+    if DEVICE_CACHE.lock().unwrap().remove(device_name).is_some() {
+        log::info!("evicted v4l2 device: {}", device_name);
+    }
+}
+
+#[derive(Debug)]
+pub struct V4L2Capture {
+    device_name: String,
+    device: Arc<Mutex<videoio::VideoCapture>>,
+    last: std::time::Instant,
+}
+
+#[async_trait]
+impl Capture for V4L2Capture {
+    fn new(device_name: &str) -> anyhow::Result<Self> {
+        log::info!("V4L2Capture::new({})", device_name);
+        let device = open_v4l2_device(device_name)?;
+
+        Ok(Self {
+            device_name: device_name.to_owned(),
+            device,
+            last: std::time::Instant::now(),
+        })
+    }
+
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+        let img = capture_with_opencv(&mut self.device.lock().unwrap())?;
+        self.last = std::time::Instant::now();
+        Ok(Some(img))
+    }
+
+    fn get_last(&self) -> std::time::Instant {
+        self.last
+    }
+
+    fn evict(&self) {
+        evict_v4l2_device(&self.device_name);
+    }
+}
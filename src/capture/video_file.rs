@@ -0,0 +1,117 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use image::RgbImage;
+use opencv::prelude::{Mat, VideoCaptureTrait, VideoCaptureTraitConst};
+use opencv::videoio::{self, CAP_PROP_FPS};
+
+use crate::capture_raw::mat_to_rgb_image;
+
+use super::Capture;
+
+// fpsが取得できない動画の場合に使う値
+const DEFAULT_FPS: f64 = 30.0;
+// 終端に達した後、次に読もうとするまで待つ時間
+const FINISHED_WAIT: Duration = Duration::from_secs(1);
+
+/// 録画済みの動画ファイル(mp4/mkvなど)をキャプチャデバイスの代わりに読み込む
+pub struct VideoFileCapture {
+    video: Mutex<videoio::VideoCapture>,
+    // Noneの場合は待たずに次のフレームを読む
+    frame_interval: Option<Duration>,
+    fps: f64,
+    // これまでに読んだフレーム数
+    frames: u64,
+    finished: bool,
+    last: Instant,
+}
+
+impl VideoFileCapture {
+    /// `speed` は動画本来のfpsに対する倍率。0以下の場合はできるだけ速く読み込む
+    pub fn with_speed(path: &str, speed: f64) -> anyhow::Result<Self> {
+        log::info!("VideoFileCapture::with_speed({}, {})", path, speed);
+        let video = videoio::VideoCapture::from_file(path, videoio::CAP_ANY)?;
+        if !videoio::VideoCapture::is_opened(&video)? {
+            return Err(anyhow::anyhow!("Unable to open video file: {}", path));
+        }
+        let fps = match video.get(CAP_PROP_FPS)? {
+            fps if fps.is_finite() && fps > 0.0 => fps,
+            _ => DEFAULT_FPS,
+        };
+        log::info!("video: {} {}fps", path, fps);
+        let frame_interval = if speed > 0.0 {
+            Some(Duration::from_secs_f64(1.0 / (fps * speed)))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            video: Mutex::new(video),
+            frame_interval,
+            fps,
+            frames: 0,
+            finished: false,
+            last: Instant::now(),
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[async_trait]
+impl Capture for VideoFileCapture {
+    fn new(path: &str) -> anyhow::Result<Self> {
+        Self::with_speed(path, 1.0)
+    }
+
+    async fn capture(&mut self) -> anyhow::Result<Option<RgbImage>> {
+        if self.finished {
+            return Ok(None);
+        }
+        let img = {
+            let mut frame = Mat::default();
+            if !self.video.get_mut().unwrap().read(&mut frame)? {
+                log::info!("reached the end of the video file");
+                self.finished = true;
+                return Ok(None);
+            }
+            mat_to_rgb_image(&frame)?
+        };
+        self.frames += 1;
+        self.last = Instant::now();
+        Ok(Some(img))
+    }
+
+    fn get_last(&self) -> Instant {
+        self.last
+    }
+
+    fn stream_time(&self) -> Option<Duration> {
+        let index = self.frames.checked_sub(1)?;
+        Some(Duration::from_secs_f64(index as f64 / self.fps))
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    // 動画のfpsと再生速度に合わせて駆動
+    // フレームを間引くと再生位置がずれるので、frame_rateは使わない
+    async fn sleep(&self, _frame_rate: f64) {
+        // 終端に達したら読むものはないので、ループが空回りしないように待つ
+        if self.finished {
+            tokio::time::sleep(FINISHED_WAIT).await;
+            return;
+        }
+        let Some(frame_interval) = self.frame_interval else {
+            return;
+        };
+        let now = Instant::now();
+        if now < self.get_last() + frame_interval {
+            tokio::time::sleep(frame_interval - (now - self.get_last())).await;
+        }
+    }
+}
@@ -1,21 +1,35 @@
 use eframe::epaint::ahash::HashMap;
+#[cfg(windows)]
 use escapi::Device;
+use image::ImageBuffer;
+#[cfg(windows)]
+use image::Rgb;
 use image::RgbImage;
-use image::{ImageBuffer, Rgb};
 use opencv::prelude::Mat;
 use opencv::prelude::MatTraitConstManual;
 use opencv::prelude::VideoCaptureTrait;
 use opencv::videoio::VideoCapture;
+#[cfg(windows)]
 use windows::core::Interface;
+#[cfg(windows)]
 use windows::w;
+#[cfg(windows)]
 use windows::Win32::Media::DirectShow::ICreateDevEnum;
+#[cfg(windows)]
 use windows::Win32::Media::MediaFoundation::CLSID_SystemDeviceEnum;
+#[cfg(windows)]
 use windows::Win32::Media::MediaFoundation::CLSID_VideoInputDeviceCategory;
+#[cfg(windows)]
 use windows::Win32::System::Com::CoCreateInstance;
+#[cfg(windows)]
 use windows::Win32::System::Com::CoInitialize;
+#[cfg(windows)]
 use windows::Win32::System::Com::IEnumMoniker;
+#[cfg(windows)]
 use windows::Win32::System::Com::IMoniker;
+#[cfg(windows)]
 use windows::Win32::System::Com::StructuredStorage::IPropertyBag;
+#[cfg(windows)]
 use windows::Win32::System::Com::CLSCTX_INPROC_SERVER;
 
 use crate::size::HEIGHT;
@@ -24,8 +38,13 @@ use crate::size::WIDTH;
 pub fn capture_with_opencv(cam: &mut VideoCapture) -> anyhow::Result<RgbImage> {
     let mut frame = Mat::default();
     cam.read(&mut frame)?;
+    mat_to_rgb_image(&frame)
+}
+
+// BGRのMatをRGBに変換し、WIDTH x HEIGHTでなければリサイズする
+pub fn mat_to_rgb_image(frame: &Mat) -> anyhow::Result<RgbImage> {
     let mut rgb = Mat::default();
-    opencv::imgproc::cvt_color(&frame, &mut rgb, opencv::imgproc::COLOR_BGR2RGB, 0)?;
+    opencv::imgproc::cvt_color(frame, &mut rgb, opencv::imgproc::COLOR_BGR2RGB, 0)?;
     if rgb.data_bytes()?.len() != WIDTH * HEIGHT * 3 {
         let mut resized = Mat::default();
         // resize
@@ -53,9 +72,12 @@ pub fn capture_with_opencv(cam: &mut VideoCapture) -> anyhow::Result<RgbImage> {
     Ok(ImageBuffer::from_raw(WIDTH as _, HEIGHT as _, buffer).unwrap())
 }
 
+#[cfg(windows)]
 pub fn capture_with_escapi(cam: &Device) -> anyhow::Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
     let (width, height) = (cam.capture_width(), cam.capture_height());
-    let pixels = cam.capture().expect("capture failed");
+    let pixels = cam
+        .capture()
+        .map_err(|e| anyhow::anyhow!("capture failed: {:?}", e))?;
 
     let mut buffer = vec![0; width as usize * height as usize * 3];
     for i in 0..pixels.len() / 4 {
@@ -67,6 +89,7 @@ pub fn capture_with_escapi(cam: &Device) -> anyhow::Result<ImageBuffer<Rgb<u8>,
     Ok(ImageBuffer::from_raw(width as _, height as _, buffer).unwrap())
 }
 
+#[cfg(windows)]
 fn get_friendly_names() -> anyhow::Result<Vec<String>> {
     let mut friendly_names = Vec::new();
 
@@ -116,6 +139,7 @@ fn get_friendly_names() -> anyhow::Result<Vec<String>> {
     Ok(friendly_names)
 }
 
+#[cfg(windows)]
 pub fn get_msmf_device_name_map() -> anyhow::Result<HashMap<usize, String>> {
     let device_num = escapi::num_devices();
     let device_map = (0..device_num)
@@ -129,6 +153,7 @@ pub fn get_msmf_device_name_map() -> anyhow::Result<HashMap<usize, String>> {
     Ok(device_map)
 }
 
+#[cfg(windows)]
 pub fn get_directshow_device_name_map() -> anyhow::Result<HashMap<usize, String>> {
     let mut friendly_names = get_friendly_names()?;
     let device_map = (0..friendly_names.len())
@@ -137,3 +162,35 @@ pub fn get_directshow_device_name_map() -> anyhow::Result<HashMap<usize, String>
 
     Ok(device_map)
 }
+
+// /sys/class/video4linux/videoN/name からデバイス名を取得する
+// 1つのデバイスがメタデータ用のノードも持つことがあるので、indexが0のノードだけを対象にする
+#[cfg(target_os = "linux")]
+pub fn get_v4l2_device_name_map() -> anyhow::Result<HashMap<usize, String>> {
+    let mut device_map = HashMap::default();
+    // デバイスがひとつもない場合はディレクトリ自体が存在しない
+    let entries = match std::fs::read_dir("/sys/class/video4linux") {
+        Ok(entries) => entries,
+        Err(_) => return Ok(device_map),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let device_index = match path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix("video"))
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(index) => index,
+            None => continue,
+        };
+        let node_index = std::fs::read_to_string(path.join("index")).unwrap_or_default();
+        if node_index.trim() != "0" {
+            continue;
+        }
+        let name = std::fs::read_to_string(path.join("name"))?;
+        device_map.insert(device_index, name.trim().to_string());
+    }
+
+    Ok(device_map)
+}
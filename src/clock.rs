@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+/// 実時間のソースのフレームにタイムスタンプを付ける時計
+/// 動画ファイルなどは時計ではなくソースの再生位置を使うので、速く流しても検出結果は変わらない
+pub trait Clock: Send + Sync {
+    /// 時計を作ってからの経過時間
+    fn now(&self) -> Duration;
+}
+
+/// 実時間の時計
+pub struct SystemClock {
+    started: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// テスト用の、手動で進める時計。clone したものは同じ時間を指す
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockClock {
+    now: std::sync::Arc<std::sync::Mutex<Duration>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::default();
+        let shared = clock.clone();
+        assert_eq!(clock.now(), Duration::ZERO);
+        shared.advance(Duration::from_secs(3));
+        assert_eq!(clock.now(), Duration::from_secs(3));
+    }
+}
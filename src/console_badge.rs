@@ -0,0 +1,235 @@
+use std::path::Path;
+
+use image::{imageops, GrayImage, RgbImage};
+use once_cell::sync::Lazy;
+
+use crate::courses::Console;
+use crate::ocr::Rect;
+use crate::size::{HEIGHT, WIDTH};
+
+// コース名の前に表示されるシリーズ名(GC、3DSなど)を探す範囲。コース名の帯の左側
+const CONSOLE_BADGE_TOP: u32 = (950.0 / 1080.0 * HEIGHT as f64) as u32;
+pub const CONSOLE_BADGE_REGION: Rect = Rect::new(
+    0,
+    CONSOLE_BADGE_TOP,
+    WIDTH as u32 / 3,
+    HEIGHT as u32 - CONSOLE_BADGE_TOP,
+);
+// 計算量を減らすために、縮小してから探す
+const SCALE: u32 = 2;
+// 一致度がこれ未満なら、シリーズ名は見つからなかったことにする
+const BADGE_THRESHOLD: f32 = 0.8;
+
+// シリーズ名の画像を置くディレクトリ。WIDTH x HEIGHT のロード画面から切り出したもの
+// 新コース(Console::New)にはシリーズ名がないので画像もない
+const CONSOLE_BADGE_DIR: &str = "assets/console_badges";
+const CONSOLE_BADGE_FILES: [(Console, &str); 8] = [
+    (Console::SFC, "sfc.png"),
+    (Console::GBA, "gba.png"),
+    (Console::N64, "n64.png"),
+    (Console::GC, "gc.png"),
+    (Console::DS, "ds.png"),
+    (Console::Wii, "wii.png"),
+    (Console::_3DS, "3ds.png"),
+    (Console::Tour, "tour.png"),
+];
+
+// 画像がないシリーズは見つからないだけで、OCRの結果から推測する
+static CONSOLE_BADGES: Lazy<ConsoleBadges> =
+    Lazy::new(|| ConsoleBadges::load(Path::new(CONSOLE_BADGE_DIR)));
+
+/// ロード画面のシリーズ名の画像から、どのシリーズのコースかを調べる
+/// 見つからなかった場合はNone。新コースの場合もNoneになる
+pub fn detect_console(frame: &RgbImage) -> Option<Console> {
+    let (console, score) = CONSOLE_BADGES.best_match(frame)?;
+    log::trace!("console badge: {console:?} ({score:.3})");
+    if score < BADGE_THRESHOLD {
+        return None;
+    }
+    log::debug!("console with badge: {console:?} ({score:.3})");
+    Some(console)
+}
+
+// 縮小したグレースケールの画素。平均を引いて正規化してある
+struct Template {
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>,
+}
+
+impl Template {
+    fn new(image: &GrayImage) -> Template {
+        let image = shrink(image);
+        let mut pixels = image.pixels().map(|p| p.0[0] as f32).collect::<Vec<f32>>();
+        let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+        pixels.iter_mut().for_each(|p| *p -= mean);
+        let norm = pixels.iter().map(|p| p * p).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            pixels.iter_mut().for_each(|p| *p /= norm);
+        }
+        Template {
+            width: image.width(),
+            height: image.height(),
+            pixels,
+        }
+    }
+
+    // `image` の上をずらしながら正規化相互相関を計算し、一番大きい値を返す
+    fn best_score(&self, image: &GrayImage) -> f32 {
+        if image.width() < self.width || image.height() < self.height {
+            return -1.0;
+        }
+        let area = (self.width * self.height) as f32;
+        let mut best = -1.0f32;
+        for top in 0..=image.height() - self.height {
+            for left in 0..=image.width() - self.width {
+                let window = (0..self.height).flat_map(|y| {
+                    (0..self.width).map(move |x| image.get_pixel(left + x, top + y).0[0] as f32)
+                });
+                let (mut sum, mut square_sum, mut dot) = (0.0f32, 0.0f32, 0.0f32);
+                for (p, t) in window.zip(&self.pixels) {
+                    sum += p;
+                    square_sum += p * p;
+                    dot += p * t;
+                }
+                // テンプレートの平均は0なので、dotから窓の平均を引く必要はない
+                let norm = (square_sum - sum * sum / area).max(0.0).sqrt();
+                if norm > 0.0 {
+                    best = best.max(dot / norm);
+                }
+            }
+        }
+        best
+    }
+}
+
+fn shrink(image: &GrayImage) -> GrayImage {
+    imageops::resize(
+        image,
+        (image.width() / SCALE).max(1),
+        (image.height() / SCALE).max(1),
+        imageops::FilterType::Triangle,
+    )
+}
+
+struct ConsoleBadges {
+    templates: Vec<(Console, Template)>,
+}
+
+impl ConsoleBadges {
+    fn new(badges: Vec<(Console, GrayImage)>) -> ConsoleBadges {
+        let templates = badges
+            .iter()
+            .map(|(console, image)| (*console, Template::new(image)))
+            .collect();
+        ConsoleBadges { templates }
+    }
+
+    fn load(dir: &Path) -> ConsoleBadges {
+        let badges = CONSOLE_BADGE_FILES
+            .iter()
+            .filter_map(|(console, file)| {
+                let path = dir.join(file);
+                match image::open(&path) {
+                    Ok(image) => Some((*console, image.to_luma8())),
+                    Err(e) => {
+                        log::warn!("failed to load console badge {}: {:?}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<(Console, GrayImage)>>();
+        log::info!(
+            "loaded {} console badges from {}",
+            badges.len(),
+            dir.display()
+        );
+        ConsoleBadges::new(badges)
+    }
+
+    // 一番似ているシリーズと、その一致度(-1.0から1.0)を返す
+    fn best_match(&self, frame: &RgbImage) -> Option<(Console, f32)> {
+        let region = CONSOLE_BADGE_REGION;
+        let cropped = imageops::crop_imm(frame, region.x, region.y, region.width, region.height);
+        let gray = shrink(&imageops::grayscale(&cropped.to_image()));
+        self.templates
+            .iter()
+            .map(|(console, template)| (*console, template.best_score(&gray)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 黒地に白い縦線を並べた、シリーズ名の代わりの画像
+    fn badge(bars: &[u32]) -> GrayImage {
+        GrayImage::from_fn(60, 30, |x, y| {
+            let on = (4..26).contains(&y) && bars.iter().any(|b| (b * 10..b * 10 + 4).contains(&x));
+            image::Luma([if on { 255 } else { 0 }])
+        })
+    }
+
+    // コース名の帯の (x, y) の位置にシリーズ名を描いたフレーム
+    fn frame(badge: &GrayImage, x: u32, y: u32) -> RgbImage {
+        let mut frame = RgbImage::new(WIDTH as u32, HEIGHT as u32);
+        let region = CONSOLE_BADGE_REGION;
+        for (bx, by, p) in badge.enumerate_pixels() {
+            let v = p.0[0];
+            frame.put_pixel(region.x + x + bx, region.y + y + by, image::Rgb([v, v, v]));
+        }
+        frame
+    }
+
+    #[test]
+    fn test_best_match() {
+        let gc = badge(&[0, 2, 3]);
+        let ds = badge(&[1, 4]);
+        let badges = ConsoleBadges::new(vec![(Console::GC, gc.clone()), (Console::DS, ds.clone())]);
+
+        // 位置が多少ずれていても見つける
+        let (console, score) = badges.best_match(&frame(&gc, 40, 20)).unwrap();
+        assert_eq!(console, Console::GC);
+        assert!(score > 0.95);
+        let (console, score) = badges.best_match(&frame(&ds, 10, 30)).unwrap();
+        assert_eq!(console, Console::DS);
+        assert!(score > 0.95);
+
+        // シリーズ名がない場合は一致度が低い
+        let blank = RgbImage::new(WIDTH as u32, HEIGHT as u32);
+        let (_, score) = badges.best_match(&blank).unwrap();
+        assert!(score < BADGE_THRESHOLD);
+
+        // 画像がなければ何も見つけない
+        let badges = ConsoleBadges::load(Path::new("no-such-console-badges"));
+        assert!(badges.best_match(&blank).is_none());
+    }
+
+    // 実際のロード画面(1280x720)を test_assets/loading_screens_by_console/<シリーズ>/ に置く
+    // 新コースは New/ に置き、シリーズ名が見つからないことを確かめる
+    #[test]
+    #[ignore = "needs real loading screens in test_assets/loading_screens_by_console"]
+    fn test_detect_console_real_loading_screens() -> anyhow::Result<()> {
+        let consoles = CONSOLE_BADGE_FILES
+            .iter()
+            .map(|(console, _)| (console.to_string(), Some(*console)))
+            .chain([("New".to_string(), None)]);
+        let mut count = 0;
+        for (name, expected) in consoles {
+            let dir = Path::new("./test_assets/loading_screens_by_console").join(name);
+            if !dir.exists() {
+                continue;
+            }
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                let frame = image::open(&path)?.to_rgb8();
+                assert_eq!(frame.dimensions(), (WIDTH as u32, HEIGHT as u32));
+                assert_eq!(detect_console(&frame), expected, "{}", path.display());
+                count += 1;
+            }
+        }
+        assert!(count > 0);
+        Ok(())
+    }
+}
@@ -1,57 +1,127 @@
-use std::{fs::File, io::Write};
+use std::{fs::File, io::Write, time::Duration};
 
 use fps_counter::FPSCounter;
-use image::{ImageBuffer, Rgb};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
-    detector::{CourseDetector, Detector, RaceFinishDetector},
+    course_preview::COURSE_PREVIEWS,
+    courses::{is_exact_course_name, Course},
+    detector::{Arbiter, DetectorSet, MogiState, ResultsTableReader, Screenshot},
+    frame_slot::FrameReceiver,
     gui::Event,
+    learned_aliases::LEARNED_ALIASES,
     mogi_result::MogiResult,
+    ocr::{Ocr, OcrPool},
+    recorder::{dump_in_background, FrameRecorder},
 };
 
-#[derive(Debug)]
-pub struct Consumer;
+// OCRを動かすタスクの数
+const OCR_WORKERS: usize = 2;
+// これより時間がかかったOCRは失敗として扱う
+const OCR_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Consumer {
+    recorder: FrameRecorder,
+    ocr: OcrPool,
+}
 
 impl Consumer {
+    /// OCRのタスクを起動するので、tokioのランタイム上で呼ぶ
+    pub fn new(recorder: FrameRecorder, ocr: Box<dyn Ocr>) -> Consumer {
+        Consumer {
+            recorder,
+            ocr: OcrPool::new(ocr, OCR_WORKERS, OCR_TIMEOUT),
+        }
+    }
+
     pub async fn run(
         &mut self,
         mogi_result: &mut MogiResult,
-        mut rx: Receiver<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+        mut rx: FrameReceiver,
         to_gui_tx: Sender<MogiResult>,
         mut from_gui_rx: Receiver<Event>,
+        frame_rate_tx: Sender<Option<f64>>,
     ) -> anyhow::Result<()> {
         log::info!("consumer");
         to_gui_tx.send(mogi_result.clone()).await?;
         let mut a = FPSCounter::default();
         let mut i = 0;
         let mut last_mogi_state = mogi_result.clone();
-        let mut detector: Box<dyn Detector + Send + Sync> =
-            if mogi_result.current_course().is_some() {
-                Box::new(RaceFinishDetector::new())
-            } else {
-                Box::new(CourseDetector::new())
-            };
-        while let Some(buffer) = rx.recv().await {
+        let mut detectors = DetectorSet::new();
+        let mut table_reader = ResultsTableReader::new();
+        let mut arbiter = if mogi_result.current_course().is_some() {
+            Arbiter::new(MogiState::RaceFinish)
+        } else {
+            Arbiter::new(MogiState::Course)
+        };
+        let stats = rx.stats();
+        // Producerに最後に伝えたfps。Noneは設定のfps
+        let mut requested_frame_rate = None;
+        while let Some(frame) = rx.recv().await {
             if i % 30 == 0 {
                 log::trace!("fps: {:?}", a.tick());
             } else {
                 a.tick();
             }
-            if let Ok(Event::EditMogiResult(new_mogi_result)) = from_gui_rx.try_recv() {
-                if mogi_result.current_course().is_none()
-                    && new_mogi_result.current_course().is_some()
-                {
-                    log::info!(
-                        "current course has manually changed: {:?}",
-                        new_mogi_result.current_course()
-                    );
-                    detector = Box::new(RaceFinishDetector::new());
+            self.recorder.push(&frame);
+            match from_gui_rx.try_recv() {
+                Ok(Event::EditMogiResult(new_mogi_result)) => {
+                    if arbiter.state() == MogiState::Course
+                        && mogi_result.current_course().is_none()
+                        && new_mogi_result.current_course().is_some()
+                    {
+                        log::info!(
+                            "current course has manually changed: {:?}",
+                            new_mogi_result.current_course()
+                        );
+                        arbiter.force(MogiState::RaceFinish, frame.timestamp());
+                    }
+                    // Clearは修正ではないので、レース数が同じ場合だけ見る
+                    if new_mogi_result != *mogi_result
+                        && new_mogi_result.iter_races().len() == mogi_result.iter_races().len()
+                    {
+                        if let Some(frames) = self.recorder.take_recent_detection(frame.timestamp())
+                        {
+                            log::warn!("edited right after detection, dumping frames");
+                            dump_in_background(frames, "manually edited right after detection");
+                        }
+                    }
+                    learn_corrections(mogi_result, &new_mogi_result);
+                    *mogi_result = new_mogi_result;
                 }
-                *mogi_result = new_mogi_result;
+                Ok(Event::DumpRecording) => {
+                    if self.recorder.is_enabled() {
+                        self.recorder.dump("requested from gui");
+                    } else {
+                        log::warn!("recorder is disabled, set recorder_seconds in settings");
+                    }
+                }
+                Err(_) => {}
             }
 
-            detector = detector.detect(&buffer, mogi_result).await?;
+            let before_detect = mogi_result.clone();
+            let observations = detectors
+                .observe(&frame, &self.ocr, arbiter.state())
+                .await?;
+            if let Some(screenshot) = arbiter.apply(&frame, &observations, mogi_result) {
+                mogi_result.save_result_image(frame.image(), screenshot.prefix())?;
+                // 順位を記録したレースに、点数の加算が終わった表を残す
+                if let (Screenshot::Race, Some(index)) =
+                    (screenshot, mogi_result.iter_races().len().checked_sub(1))
+                {
+                    table_reader.start(index);
+                }
+            }
+            table_reader.observe(&frame, &self.ocr, mogi_result);
+            if mogi_result != &before_detect {
+                self.recorder.mark_detection(frame.timestamp());
+            }
+            if arbiter.frame_rate() != requested_frame_rate
+                && frame_rate_tx.try_send(arbiter.frame_rate()).is_ok()
+            {
+                log::debug!("requested frame rate: {:?}", arbiter.frame_rate());
+                requested_frame_rate = arbiter.frame_rate();
+            }
             if mogi_result != &last_mogi_state {
                 log::debug!("mogi: {:?}", mogi_result);
                 last_mogi_state = mogi_result.clone();
@@ -67,105 +137,123 @@ impl Consumer {
                     mogi_result.save_result()?;
                 }
             }
+            if i % 300 == 0 {
+                log::debug!(
+                    "frames from {}: processed {}, dropped {}",
+                    frame.source_id(),
+                    stats.processed(),
+                    stats.dropped()
+                );
+            }
             i += 1;
         }
         Ok(())
     }
 }
 
+// 直されたコースごとに (そのときのOCRの文字列, 直される前のコース, 直したコース) を返す
+// GUIは少し前の結果を直しているので、その間に検出が進んでOCRの文字列が変わっていれば、別のコースを直したものとして扱わない
+// コース名を正しく読めていてシリーズだけを直した場合は、同じ名前の他のシリーズのコースまで直してしまうので覚えない
+fn find_corrections<'a>(
+    old: &'a MogiResult,
+    new: &MogiResult,
+) -> Vec<(&'a [String], Option<Course>, Course)> {
+    let mut corrections = old
+        .iter_races()
+        .zip(new.iter_races())
+        .filter(|(o, n)| o.course() != n.course() && o.ocr_texts() == n.ocr_texts())
+        .filter_map(|(o, n)| Some((o.ocr_texts(), o.course(), n.course()?)))
+        .collect::<Vec<_>>();
+    if let (Some(o), Some(n)) = (old.current_course(), new.current_course()) {
+        if o != n
+            && old.iter_races().len() == new.iter_races().len()
+            && old.current_ocr_texts() == new.current_ocr_texts()
+        {
+            corrections.push((old.current_ocr_texts(), Some(o.clone()), n.clone()));
+        }
+    }
+    corrections.retain(|(texts, _, _)| !texts.iter().any(|t| is_exact_course_name(t)));
+    corrections
+}
+
+// 手動で直されたコースを、そのときのOCRの文字列と一緒に覚える
+// 直される前のコースとして覚えたプレビューは間違っているので捨てる
+fn learn_corrections(old: &MogiResult, new: &MogiResult) {
+    for (texts, wrong, course) in find_corrections(old, new) {
+        if let Some(wrong) = wrong {
+            if let Err(e) = COURSE_PREVIEWS.lock().unwrap().forget(&wrong) {
+                log::error!("failed to forget course preview: {:?}", e);
+            }
+        }
+        if let Err(e) = LEARNED_ALIASES.lock().unwrap().learn(texts, &course) {
+            log::error!("failed to save learned aliases: {:?}", e);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    extern crate ffmpeg_next as ffmpeg;
+    use std::time::Duration;
 
-    use std::path::Path;
+    use crate::capture::{Capture, VideoFileCapture};
+    use crate::courses::{Console, Course};
+    use crate::frame::Frame;
+    use crate::frame_slot::frame_slot;
+    use crate::mogi_result::MogiResult;
+    use crate::ocr::create_ocr;
+    use crate::race_result::Position;
+    use crate::recorder::FrameRecorder;
+    use crate::settings::Settings;
 
-    use ffmpeg::format::{input, Pixel};
-    use ffmpeg::media::Type;
-    use ffmpeg::software::scaling::{context::Context, flag::Flags};
-    use ffmpeg::util::frame::video::Video;
-    use image::{ImageBuffer, Rgb};
+    use super::{find_corrections, Consumer};
 
-    use crate::mogi_result::MogiResult;
+    #[test]
+    fn test_find_corrections() {
+        let wario = Course::new("ワリオスタジアム".to_string(), Console::DS);
+        let yoshi = Course::new("ヨッシーサーキット".to_string(), Console::GC);
+        let mut old = MogiResult::new();
+        old.set_current_course(wario.clone());
+        old.set_current_ocr_texts(vec!["DS ワリオスタシアム".to_string()]);
 
-    use super::Consumer;
-
-    fn decode_video<P: AsRef<Path>>(
-        path: &P,
-    ) -> anyhow::Result<Vec<ImageBuffer<Rgb<u8>, Vec<u8>>>> {
-        let mut frames = Vec::new();
-        let mut ictx = input(path)?;
-        let input = ictx
-            .streams()
-            .best(Type::Video)
-            .ok_or(ffmpeg::Error::StreamNotFound)?;
-        let video_stream_index = input.index();
-
-        let context_decoder = ffmpeg::codec::context::Context::from_parameters(input.parameters())?;
-        let mut decoder = context_decoder.decoder().video()?;
-
-        let mut scaler = Context::get(
-            decoder.format(),
-            decoder.width(),
-            decoder.height(),
-            Pixel::RGB24,
-            decoder.width(),
-            decoder.height(),
-            Flags::BILINEAR,
-        )?;
-
-        let mut frame_index = 0;
-
-        fn receive_and_process_decoded_frames(
-            decoder: &mut ffmpeg::decoder::Video,
-            scaler: &mut Context,
-            frames_vec: &mut Vec<ImageBuffer<Rgb<u8>, Vec<u8>>>,
-            frame_index: &mut i32,
-        ) -> Result<(), ffmpeg::Error> {
-            let mut decoded: Video = Video::empty();
-            while decoder.receive_frame(&mut decoded).is_ok() {
-                let mut rgb_frame = Video::empty();
-                scaler.run(&decoded, &mut rgb_frame)?;
-                let rgb_buffer = rgb_frame.data(0);
-                if let Some(image_buffer) = ImageBuffer::from_raw(
-                    rgb_frame.width(),
-                    rgb_frame.height(),
-                    rgb_buffer.to_vec(),
-                ) {
-                    log::debug!("frame_index: {}", frame_index);
-                    frames_vec.push(image_buffer);
-                }
-                *frame_index += 1;
-            }
-            Ok(())
-        }
+        let mut new = old.clone();
+        new.correct_current_course(yoshi.clone());
+        assert_eq!(
+            find_corrections(&old, &new),
+            vec![(
+                &["DS ワリオスタシアム".to_string()][..],
+                Some(wario.clone()),
+                yoshi.clone()
+            )]
+        );
 
-        for (stream, packet) in ictx.packets() {
-            if stream.index() == video_stream_index {
-                decoder.send_packet(&packet)?;
-                receive_and_process_decoded_frames(
-                    &mut decoder,
-                    &mut scaler,
-                    &mut frames,
-                    &mut frame_index,
-                )?;
-            }
-        }
-        decoder.send_eof()?;
-        receive_and_process_decoded_frames(
-            &mut decoder,
-            &mut scaler,
-            &mut frames,
-            &mut frame_index,
-        )?;
-        Ok(frames)
+        // GUIが直している間に次のレースに進んでいたら、今のコースの文字列として覚えない
+        let mut moved = old.clone();
+        moved.set_current_position(Position::First);
+        moved.set_current_course(wario.clone());
+        moved.set_current_ocr_texts(vec!["DS ワリオスタジアム".to_string()]);
+        assert!(find_corrections(&moved, &new).is_empty());
+
+        // 記録済みのレースは、同じレースを直した場合だけ覚える
+        let mut new = moved.clone();
+        new.set_course(0, yoshi.clone());
+        assert_eq!(find_corrections(&moved, &new).len(), 1);
+
+        // コース名は正しく読めていて、シリーズだけを直した場合は覚えない
+        let mut old = MogiResult::new();
+        old.set_current_course(Course::new("レインボーロード".to_string(), Console::New));
+        old.set_current_ocr_texts(vec!["レインボーロード".to_string()]);
+        let mut new = old.clone();
+        new.correct_current_course(Course::new("レインボーロード".to_string(), Console::N64));
+        assert!(find_corrections(&old, &new).is_empty());
     }
 
     #[tokio::test]
     async fn test() -> anyhow::Result<()> {
         let mut mogi_result = MogiResult::new();
-        ffmpeg::init().unwrap();
         let (_from_gui_tx, from_gui_rx) = tokio::sync::mpsc::channel(10);
         let (to_gui_tx, mut to_gui_rx) = tokio::sync::mpsc::channel(10);
+        // 動画ファイルはfpsの要求を無視するので、受け取る側は用意しない
+        let (frame_rate_tx, _frame_rate_rx) = tokio::sync::mpsc::channel(10);
 
         let _ = tokio::task::spawn(async move {
             while let Some(event) = to_gui_rx.recv().await {
@@ -174,21 +262,29 @@ mod test {
         })
         .await;
 
-        let mut consumer = Consumer;
-        let (tx, rx) = tokio::sync::mpsc::channel(10);
-        let frames = tokio::task::spawn_blocking(move || {
-            decode_video(&"./test_assets/input.mp4".to_string()).unwrap()
-        })
-        .await?;
+        let settings = Settings::new("".to_string(), false, "INFO".to_string(), false);
+        let ocr = create_ocr(&settings);
+        let mut consumer = Consumer::new(FrameRecorder::new(Duration::ZERO), ocr);
+        let (tx, rx) = frame_slot();
+        // 再生速度0で動画を最速で流す
+        let mut capture = VideoFileCapture::with_speed("./test_assets/input.mp4", 0.0)?;
 
         let producer = tokio::task::spawn(async move {
-            for frame in frames {
-                tx.send(frame).await.unwrap();
+            let source_id: std::sync::Arc<str> = "video:./test_assets/input.mp4".into();
+            let mut sequence = 0;
+            while !capture.is_finished() {
+                if let Some(img) = capture.capture().await.unwrap() {
+                    // 動画上の再生位置をタイムスタンプにするので、読み込み速度に関係なく結果が変わらない
+                    let timestamp = capture.stream_time().unwrap();
+                    let frame = Frame::new(img, timestamp, sequence, source_id.clone());
+                    tx.send_lossless(frame).await.unwrap();
+                    sequence += 1;
+                }
             }
         });
         let consumer = tokio::task::spawn(async move {
             consumer
-                .run(&mut mogi_result, rx, to_gui_tx, from_gui_rx)
+                .run(&mut mogi_result, rx, to_gui_tx, from_gui_rx, frame_rate_tx)
                 .await
                 .unwrap();
         });
@@ -196,7 +292,6 @@ mod test {
         producer.await?;
         consumer.await?;
 
-        // mp4 to ImageBuffers
         Ok(())
     }
 }
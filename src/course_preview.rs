@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use image::{imageops, RgbImage};
+use once_cell::sync::Lazy;
+
+use crate::courses::{Course, STRING_COURSE_MAP};
+use crate::ocr::Rect;
+use crate::size::{HEIGHT, WIDTH};
+
+// コースごとのプレビュー画像を置くディレクトリ。ファイル名は "GC ヨッシーサーキット.png" のようにコースの表示名にする
+const COURSE_PREVIEW_DIR: &str = "course_previews";
+// 実際のロード画面から切り出して配布するプレビュー。ファイル名の付け方は同じ
+// OCRで覚えたものより優先し、書き換えない
+const BUNDLED_PREVIEW_DIR: &str = "assets/course_previews";
+// 右下のコースプレビュー。1920x1080基準
+pub const COURSE_PREVIEW_REGION: Rect = Rect::new(
+    (1480.0 / 1920.0 * WIDTH as f64) as u32,
+    (760.0 / 1080.0 * HEIGHT as f64) as u32,
+    (400.0 / 1920.0 * WIDTH as f64) as u32,
+    (190.0 / 1080.0 * HEIGHT as f64) as u32,
+);
+// 比べるときはこの大きさに縮小する
+const THUMBNAIL_WIDTH: u32 = 48;
+const THUMBNAIL_HEIGHT: u32 = 24;
+/// プレビューの一致度がこれ以上なら、OCRの結果を待たずにそのコースとする
+pub const PREVIEW_THRESHOLD: f32 = 0.9;
+
+pub static COURSE_PREVIEWS: Lazy<Mutex<CoursePreviews>> = Lazy::new(|| {
+    let previews = CoursePreviews::load(
+        Path::new(BUNDLED_PREVIEW_DIR),
+        Path::new(COURSE_PREVIEW_DIR),
+    )
+    .unwrap_or_else(|e| {
+        log::warn!("failed to load course previews: {:?}", e);
+        CoursePreviews::new(PathBuf::from(COURSE_PREVIEW_DIR))
+    });
+    Mutex::new(previews)
+});
+
+// 縮小したグレースケールの画素。平均を引いて正規化してある
+#[derive(Clone)]
+struct Thumbnail(Vec<f32>);
+
+impl Thumbnail {
+    fn new(image: &RgbImage) -> Thumbnail {
+        let small = imageops::resize(
+            image,
+            THUMBNAIL_WIDTH,
+            THUMBNAIL_HEIGHT,
+            imageops::FilterType::Triangle,
+        );
+        let mut pixels = small
+            .pixels()
+            .map(|p| p.0[0] as f32 * 0.299 + p.0[1] as f32 * 0.587 + p.0[2] as f32 * 0.114)
+            .collect::<Vec<f32>>();
+        let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+        pixels.iter_mut().for_each(|p| *p -= mean);
+        let norm = pixels.iter().map(|p| p * p).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            pixels.iter_mut().for_each(|p| *p /= norm);
+        }
+        Thumbnail(pixels)
+    }
+
+    // 正規化相互相関。同じ画像なら1で、似ていないほど小さくなる
+    fn similarity(&self, other: &Thumbnail) -> f32 {
+        self.0.iter().zip(&other.0).map(|(a, b)| a * b).sum::<f32>()
+    }
+}
+
+fn crop_preview(frame: &RgbImage) -> RgbImage {
+    let region = COURSE_PREVIEW_REGION;
+    imageops::crop_imm(frame, region.x, region.y, region.width, region.height).to_image()
+}
+
+/// ロード画面のコースプレビューとコースごとの画像を比べて、どのコースかを調べる
+/// OCRと違って言語やフォントに左右されない
+pub struct CoursePreviews {
+    // OCRで覚えたプレビューを保存するディレクトリ
+    dir: PathBuf,
+    bundled: Vec<(Course, Thumbnail)>,
+    references: Vec<(Course, Thumbnail)>,
+}
+
+impl CoursePreviews {
+    pub fn new(dir: PathBuf) -> CoursePreviews {
+        CoursePreviews {
+            dir,
+            bundled: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+
+    /// 配布したプレビューと、OCRで覚えたプレビューを読み込む
+    pub fn load(bundled_dir: &Path, dir: &Path) -> anyhow::Result<CoursePreviews> {
+        let mut previews = CoursePreviews::new(dir.to_path_buf());
+        previews.bundled = read_previews(bundled_dir)?;
+        // 配布したものがあるコースは、覚えたものを使わない
+        previews.references = read_previews(dir)?
+            .into_iter()
+            .filter(|(course, _)| !previews.bundled.iter().any(|(c, _)| c == course))
+            .collect();
+        Ok(previews)
+    }
+
+    pub fn contains(&self, course: &Course) -> bool {
+        self.bundled
+            .iter()
+            .chain(&self.references)
+            .any(|(c, _)| c == course)
+    }
+
+    /// 一番似ているコースと、その一致度(-1.0から1.0)を返す
+    pub fn identify(&self, frame: &RgbImage) -> Option<(Course, f32)> {
+        let thumbnail = Thumbnail::new(&crop_preview(frame));
+        self.bundled
+            .iter()
+            .chain(&self.references)
+            .map(|(course, reference)| (course, reference.similarity(&thumbnail)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(course, score)| (course.clone(), score))
+    }
+
+    /// OCRでコースがわかった時のフレームから、そのコースのプレビューを保存する
+    pub fn save_reference(&mut self, frame: &RgbImage, course: &Course) -> anyhow::Result<()> {
+        let preview = crop_preview(frame);
+        std::fs::create_dir_all(&self.dir)?;
+        preview.save(self.dir.join(format!("{}.png", course)))?;
+        self.references.retain(|(c, _)| c != course);
+        self.references
+            .push((course.clone(), Thumbnail::new(&preview)));
+        log::info!("saved course preview: {}", course);
+        Ok(())
+    }
+
+    /// 間違ったコースとして覚えたプレビューを捨てる。配布したものは消さない
+    pub fn forget(&mut self, course: &Course) -> anyhow::Result<()> {
+        if !self.references.iter().any(|(c, _)| c == course) {
+            return Ok(());
+        }
+        self.references.retain(|(c, _)| c != course);
+        let path = self.dir.join(format!("{}.png", course));
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        log::info!("forgot course preview: {}", course);
+        Ok(())
+    }
+}
+
+// ディレクトリにあるプレビューを読み込む。ディレクトリがなければ空
+fn read_previews(dir: &Path) -> anyhow::Result<Vec<(Course, Thumbnail)>> {
+    let mut previews = Vec::new();
+    if !dir.exists() {
+        return Ok(previews);
+    }
+    let courses = STRING_COURSE_MAP.lock().unwrap();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some("png".as_ref()) {
+            continue;
+        }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let Some(course) = courses.get(name.as_ref()) else {
+            log::warn!("unknown course preview: {}", path.display());
+            continue;
+        };
+        let image = image::open(&path)?.to_rgb8();
+        previews.push((course.clone(), Thumbnail::new(&image)));
+    }
+    log::info!(
+        "loaded {} course previews from {}",
+        previews.len(),
+        dir.display()
+    );
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::courses::Console;
+
+    use super::*;
+
+    // プレビューの位置に縞模様を描いたフレーム
+    fn frame(stripe: u32) -> RgbImage {
+        let region = COURSE_PREVIEW_REGION;
+        RgbImage::from_fn(WIDTH as u32, HEIGHT as u32, |x, y| {
+            let inside = x >= region.x && y >= region.y;
+            if inside && ((x - region.x) / stripe + (y - region.y) / stripe) & 1 == 0 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_identify() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "lounge-memo-test-course-previews-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let yoshi = Course::new("ヨッシーサーキット".to_string(), Console::GC);
+        let mks = Course::new("マリオカートスタジアム".to_string(), Console::New);
+
+        let mut previews = CoursePreviews::new(dir.clone());
+        assert!(previews.identify(&frame(10)).is_none());
+        previews.save_reference(&frame(10), &yoshi)?;
+        previews.save_reference(&frame(40), &mks)?;
+
+        // 保存したものを読み込み直しても同じように見分けられる
+        let mut previews = CoursePreviews::load(&dir.join("bundled"), &dir)?;
+        assert_eq!(previews.references.len(), 2);
+        assert!(previews.contains(&yoshi));
+        let (course, score) = previews.identify(&frame(40)).unwrap();
+        assert_eq!(course, mks);
+        assert!(score > 0.99);
+        let (course, _) = previews.identify(&frame(10)).unwrap();
+        assert_eq!(course, yoshi);
+
+        // 間違って覚えたものは捨てられる
+        previews.forget(&mks)?;
+        assert!(!previews.contains(&mks));
+        let previews = CoursePreviews::load(&dir.join("bundled"), &dir)?;
+        assert!(!previews.contains(&mks));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    // 実際のロード画面(1280x720)を "GC ヨッシーサーキット.png" のような名前で test_assets/loading_screens/ に置く
+    // 配布するプレビューで正しく見分けられ、他のコースのプレビューとは一致度が閾値に届かないことを確かめる
+    #[test]
+    #[ignore = "needs real loading screens in test_assets/loading_screens"]
+    fn test_identify_real_loading_screens() -> anyhow::Result<()> {
+        let bundled = read_previews(Path::new(BUNDLED_PREVIEW_DIR))?;
+        let screens = read_dir_frames(Path::new("./test_assets/loading_screens"))?;
+        assert!(!screens.is_empty());
+        for (expected, frame) in screens {
+            let mut previews = CoursePreviews::new(PathBuf::new());
+            previews.bundled = bundled
+                .iter()
+                .map(|(c, t)| (c.clone(), t.clone()))
+                .collect();
+            let (course, score) = previews.identify(&frame).unwrap();
+            assert_eq!(course, expected);
+            assert!(score >= PREVIEW_THRESHOLD, "{expected}: {score}");
+
+            previews.bundled.retain(|(c, _)| c != &expected);
+            if let Some((other, score)) = previews.identify(&frame) {
+                assert!(
+                    score < PREVIEW_THRESHOLD,
+                    "{expected} looks like {other}: {score}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn read_dir_frames(dir: &Path) -> anyhow::Result<Vec<(Course, RgbImage)>> {
+        let courses = STRING_COURSE_MAP.lock().unwrap();
+        let mut frames = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            let course = courses
+                .get(name.as_ref())
+                .ok_or_else(|| anyhow::anyhow!("unknown course: {}", path.display()))?;
+            let image = image::open(&path)?.to_rgb8();
+            assert_eq!(image.dimensions(), (WIDTH as u32, HEIGHT as u32));
+            frames.push((course.clone(), image));
+        }
+        Ok(frames)
+    }
+}
@@ -3,9 +3,11 @@ use std::{collections::HashMap, fmt::Display};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
-use strsim::levenshtein;
+use strsim::normalized_levenshtein;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::word::{normalize_japanese_characters, Word};
+use crate::normalize::normalize;
+use crate::word::Word;
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -708,7 +710,7 @@ pub static COURSES: Lazy<Mutex<Vec<Course>>> = Lazy::new(|| {
             COURSE_SHORTHAND_MAP
                 .lock()
                 .unwrap()
-                .insert(shorthand.to_string(), course_name.clone());
+                .insert(normalize(shorthand), course_name.clone());
         }
     }
     Mutex::new(courses)
@@ -731,7 +733,7 @@ pub static COURSES_CONSOLE_MAP_MAP: Lazy<Mutex<HashMap<Console, HashMap<String,
             let mut courses = HashMap::new();
             for course in COURSES.lock().unwrap().iter() {
                 if course.console == console {
-                    let noramlized_name = normalize_japanese_characters(course.name.clone());
+                    let noramlized_name = normalize(&course.name);
                     courses.insert(noramlized_name, course.clone());
                 }
             }
@@ -749,7 +751,10 @@ pub static STRING_COURSE_MAP: Lazy<Mutex<HashMap<String, Course>>> = Lazy::new(|
     Mutex::new(map)
 });
 
-fn get_console_by_words(words: &Vec<Word>) -> Console {
+// 推測したシリーズと違うコースの点数から引く
+const CONSOLE_MISMATCH_PENALTY: f64 = 0.1;
+
+fn get_console_by_words(words: &[Word]) -> Console {
     for word in words {
         let lower_text = word.text.to_lowercase();
         let lower_text = lower_text.trim();
@@ -782,15 +787,16 @@ fn get_console_by_words(words: &Vec<Word>) -> Console {
     Console::New
 }
 
-pub fn get_course_by_words(words: &Vec<Word>) -> Option<Course> {
-    let console = get_console_by_words(words);
+// シリーズがわかっている場合は `console` に渡す。Noneなら単語から推測する
+pub fn get_course_by_words(words: &Vec<Word>, console: Option<Console>) -> Option<Course> {
+    let console = console.unwrap_or_else(|| get_console_by_words(words));
 
     let binding = COURSES_CONSOLE_MAP_MAP.lock().unwrap();
     let course_map = binding.get(&console).unwrap();
     for word in words {
         let lower_text = word.text.to_lowercase();
         let lower_text = lower_text.trim();
-        let lower_text = normalize_japanese_characters(lower_text.to_string());
+        let lower_text = normalize(lower_text);
         if let Some(course) = course_map.get(&lower_text) {
             return Some(course.clone());
         }
@@ -798,50 +804,78 @@ pub fn get_course_by_words(words: &Vec<Word>) -> Option<Course> {
     None
 }
 
-pub fn get_course_by_words_with_nearest(words: &Vec<Word>, threshold: usize) -> Option<Course> {
-    if words.is_empty() {
-        return None;
+/// すべての単語とシリーズのコースについて、似ている順に (コース, 点数) を返す
+/// 点数は0.0から1.0で、コース名と完全に一致すれば1.0になる
+/// シリーズがわかっている場合は `console` に渡す。Noneなら単語から推測する
+pub fn rank_courses_by_words(words: &[Word], console: Option<Console>) -> Vec<(Course, f64)> {
+    let console = console.unwrap_or_else(|| get_console_by_words(words));
+    let texts = words
+        .iter()
+        .map(|w| without_spaces(&normalize(&w.text)))
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<String>>();
+    if texts.is_empty() {
+        return Vec::new();
     }
-    let console = get_console_by_words(words);
 
-    let longest_word = words
+    let mut ranked = COURSES
+        .lock()
+        .unwrap()
         .iter()
-        .max_by(|w1, w2| w1.text.len().cmp(&w2.text.len()))
-        .unwrap();
-    let normalized_longest_word = normalize_japanese_characters(longest_word.text.clone());
+        .map(|course| {
+            // 行全体を認識した場合はシリーズ名もついているので、両方と比べる
+            let names = [
+                without_spaces(&normalize(&course.name)),
+                without_spaces(&normalize(&course.to_string())),
+            ];
+            let similarity = texts
+                .iter()
+                .flat_map(|t| names.iter().map(move |n| normalized_levenshtein(t, n)))
+                .fold(0.0, f64::max);
+            let score = if course.console == console {
+                similarity
+            } else {
+                (similarity - CONSOLE_MISMATCH_PENALTY).max(0.0)
+            };
+            (course.clone(), score)
+        })
+        .collect::<Vec<(Course, f64)>>();
+    ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    ranked
+}
 
-    let binding = COURSES_CONSOLE_MAP_MAP.lock().unwrap();
-    let course_map = binding.get(&console).unwrap();
-    let course_names = course_map.keys().collect::<Vec<&String>>();
-    // レーベンシュタイン距離が最小のコースを探す
-    let mut min_distance = std::usize::MAX;
-    let mut min_course_name = None;
-    for course_name in course_names {
-        let normalized_course_name = normalize_japanese_characters(course_name.to_string());
-        let distance = levenshtein(&normalized_longest_word, &normalized_course_name);
-        if distance < min_distance {
-            min_distance = distance;
-            min_course_name = Some(course_name);
-        }
-    }
-    if min_distance > threshold {
-        return None;
-    }
-    if let Some(min_course_name) = min_course_name {
-        return course_map.get(min_course_name).cloned();
-    }
+/// `text` がシリーズ名の有無によらず、どれかのコース名と完全に一致するか
+// normalize は濁点を取り除くので、ここでは全角・半角と大文字・小文字、空白の違いだけを無視する
+pub fn is_exact_course_name(text: &str) -> bool {
+    let exact = |s: &str| without_spaces(&s.nfkc().collect::<String>().to_lowercase());
+    let text = exact(text);
+    COURSES.lock().unwrap().iter().any(|course| {
+        [course.name.clone(), course.to_string()]
+            .iter()
+            .any(|name| exact(name) == text)
+    })
+}
 
-    None
+fn without_spaces(text: &str) -> String {
+    text.chars().filter(|c| !c.is_whitespace()).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_exact_course_name() {
+        assert!(is_exact_course_name("レインボーロード"));
+        assert!(is_exact_course_name("SFC レインボーロード"));
+        assert!(is_exact_course_name("DS ワリオスタジアム"));
+        assert!(!is_exact_course_name("DS ワリオスタシアム"));
+    }
+
     #[test]
     fn test_normalize_japanese_characters() {
         assert_eq!(
-            normalize_japanese_characters("あがぱ工EｅＥ".to_string()),
+            normalize("あがぱ工EｅＥ"),
             "アカハエeee".to_string()
         );
     }
@@ -855,15 +889,16 @@ mod tests {
     }
     fn assert_vec_str_to_course(words: Vec<&str>, expected: Course) {
         let words = vec_str_to_words(words);
-        let course = get_course_by_words(&words);
+        let course = get_course_by_words(&words, None);
         assert_eq!(course, Some(expected));
     }
-    fn assert_vec_str_to_course_with_nearest(words: Vec<&str>, expected: Option<Course>) {
+    fn assert_vec_str_to_best_candidate(words: Vec<&str>, expected: Course) {
         let words = vec_str_to_words(words);
-        let course = get_course_by_words_with_nearest(&words, 3);
-        assert_eq!(course, expected);
+        let ranked = rank_courses_by_words(&words, None);
+        assert_eq!(ranked[0].0, expected);
+        // 2番目以降は点数が同じか低い
+        assert!(ranked.windows(2).all(|w| w[0].1 >= w[1].1));
     }
-
     #[test]
     fn test_get_course_by_words() {
         assert_vec_str_to_course(
@@ -889,34 +924,63 @@ mod tests {
     }
 
     #[test]
-    fn test_get_course_by_words_with_nearest() {
-        assert_vec_str_to_course_with_nearest(
+    fn test_rank_courses_by_words() {
+        assert_vec_str_to_best_candidate(
             vec!["ヨッシーサーキット", "GC"],
-            Some(Course::new("ヨッシーサーキット".to_string(), Console::GC)),
+            Course::new("ヨッシーサーキット".to_string(), Console::GC),
         );
-        assert_vec_str_to_course_with_nearest(
+        assert_vec_str_to_best_candidate(
             vec!["ックロックマウンテン", "3DS"],
-            Some(Course::new(
-                "ロックロックマウンテン".to_string(),
-                Console::_3DS,
-            )),
+            Course::new("ロックロックマウンテン".to_string(), Console::_3DS),
         );
-        assert_vec_str_to_course_with_nearest(
+        assert_vec_str_to_best_candidate(
             vec!["ノヒオサーキット", "3DS"],
-            Some(Course::new("キノピオサーキット".to_string(), Console::_3DS)),
+            Course::new("キノピオサーキット".to_string(), Console::_3DS),
         );
-        assert_vec_str_to_course_with_nearest(
+        assert_vec_str_to_best_candidate(
             vec!["うぶつの森"],
-            Some(Course::new("どうぶつの森".to_string(), Console::New)),
+            Course::new("どうぶつの森".to_string(), Console::New),
         );
-        assert_vec_str_to_course_with_nearest(
+        assert_vec_str_to_best_candidate(
             vec!["ーユーヨークドリーム", "Tour"],
-            Some(Course::new(
-                "ニューヨークドリーム".to_string(),
-                Console::Tour,
-            )),
+            Course::new("ニューヨークドリーム".to_string(), Console::Tour),
+        );
+        // 行全体を認識してシリーズ名がついていても一致する
+        let words = vec_str_to_words(vec!["GC ヨッシ一サーキット"]);
+        assert_eq!(
+            rank_courses_by_words(&words, None)[0],
+            (
+                Course::new("ヨッシーサーキット".to_string(), Console::GC),
+                1.0
+            )
+        );
+
+        let words = vec_str_to_words(vec!["あまりにもかけ離れている場合", "Tour"]);
+        assert!(rank_courses_by_words(&words, None)[0].1 < 0.5);
+        // シリーズが違っても候補には入るが、点数は下がる
+        let words = vec_str_to_words(vec!["キノコキャニオン"]);
+        let (course, score) = rank_courses_by_words(&words, None).remove(0);
+        assert_eq!(course, Course::new("キノコキャニオン".to_string(), Console::Wii));
+        assert!(score < 1.0);
+        assert!(rank_courses_by_words(&[], None).is_empty());
+    }
+
+    #[test]
+    fn test_get_course_by_words_with_console() {
+        // シリーズが渡された場合は単語から推測しない
+        let words = vec_str_to_words(vec!["ヨッシーサーキット", "ds"]);
+        assert_eq!(
+            get_course_by_words(&words, Some(Console::GC)),
+            Some(Course::new("ヨッシーサーキット".to_string(), Console::GC))
+        );
+        let words = vec_str_to_words(vec!["ワリオスタジアム"]);
+        assert_eq!(
+            rank_courses_by_words(&words, Some(Console::DS))[0],
+            (
+                Course::new("ワリオスタジアム".to_string(), Console::DS),
+                1.0
+            )
         );
-        assert_vec_str_to_course_with_nearest(vec!["あまりにもかけ離れている場合", "Tour"], None);
-        assert_vec_str_to_course_with_nearest(vec!["キノコキャニオン"], None);
+        assert_eq!(get_course_by_words(&words, None), None);
     }
 }
@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use crate::frame::Frame;
+use crate::mogi_result::MogiResult;
+
+use super::state_machine::{StateMachine, Trigger};
+use super::{FoundCourse, MogiState, Observation, ObservationKind};
+
+// リザルト画面の表がこの確からしさ以上なら、レースが終わったとする
+const RESULTS_TABLE_SCORE: f64 = 0.75;
+// 順位がこの確からしさ以上なら、順位を記録する
+const POSITION_SCORE: f64 = 1.0;
+// レース中に別のコースが見つかった場合、この確からしさ以上なら入れ替える
+const REPLACE_COURSE_SCORE: f64 = 0.95;
+// コースを探し始めてからこの時間は、リザルト画面の表を見てもコースの見逃しとしない
+// 総合順位の画面もリザルト画面の表と同じように見えるため
+const MISSED_COURSE_AFTER: Duration = Duration::from_secs(60);
+
+/// 保存するリザルト画面の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screenshot {
+    // レースの順位
+    Race,
+    // 総合順位
+    Total,
+}
+
+impl Screenshot {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Screenshot::Race => "race",
+            Screenshot::Total => "total",
+        }
+    }
+}
+
+/// すべてのDetectorが見つけたものをまとめて、今の状態からMogiResultを更新する
+pub struct Arbiter {
+    machine: StateMachine,
+}
+
+impl Arbiter {
+    pub fn new(state: MogiState) -> Arbiter {
+        Arbiter {
+            machine: StateMachine::new(state),
+        }
+    }
+
+    pub fn state(&self) -> MogiState {
+        self.machine.state()
+    }
+
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.state().frame_rate()
+    }
+
+    /// GUIからの修正などで、見つかったものによらずに状態を変える
+    pub fn force(&mut self, state: MogiState, now: Duration) {
+        self.machine.force(state, now);
+    }
+
+    /// 1フレーム分の見つかったものからMogiResultを更新する
+    /// 保存すべきリザルト画面が出ていれば、その種類を返す
+    pub fn apply(
+        &mut self,
+        frame: &Frame,
+        observations: &[Observation],
+        mogi_result: &mut MogiResult,
+    ) -> Option<Screenshot> {
+        let now = frame.timestamp();
+        if self.machine.check_timeout(now, mogi_result) {
+            return None;
+        }
+
+        let state = self.state();
+        let in_race = state == MogiState::RaceFinish || state == MogiState::Position;
+        if in_race
+            && observations
+                .iter()
+                .any(|o| o.kind == ObservationKind::ErrorDialog)
+        {
+            // このレースは記録できないので、次のレースのコースを探す
+            mogi_result.reset_current_course();
+            self.machine
+                .transition(MogiState::Course, Trigger::Error, now);
+            return None;
+        }
+
+        match state {
+            MogiState::Course => {
+                if let Some(found) = find_course(observations, 0.0) {
+                    mogi_result.set_current_course(found.course.clone());
+                    mogi_result.set_current_candidates(found.alternatives.clone());
+                    mogi_result.set_current_ocr_texts(found.ocr_texts.clone());
+                    self.machine
+                        .transition(MogiState::RaceFinish, Trigger::Detected, now);
+                } else if has_results_table(observations)
+                    && self.machine.elapsed(now) >= MISSED_COURSE_AFTER
+                {
+                    // ロード画面を見逃しても、コースがわからないまま順位を記録する
+                    log::warn!("results table without course");
+                    mogi_result.mark_current_course_missed();
+                    self.machine
+                        .transition(MogiState::Position, Trigger::Detected, now);
+                }
+            }
+            MogiState::RaceFinish => {
+                if has_results_table(observations) {
+                    self.machine
+                        .transition(MogiState::Position, Trigger::Detected, now);
+                } else if let Some(found) = find_course(observations, REPLACE_COURSE_SCORE) {
+                    // リザルト画面を見逃して次のロード画面が出たか、OCRの推測より確かなものが見つかった
+                    if mogi_result.current_course().as_ref() != Some(&found.course) {
+                        log::warn!("course has changed during race: {}", found.course);
+                        mogi_result.set_current_course(found.course.clone());
+                        mogi_result.set_current_candidates(found.alternatives.clone());
+                        mogi_result.set_current_ocr_texts(found.ocr_texts.clone());
+                        self.machine
+                            .transition(MogiState::RaceFinish, Trigger::Detected, now);
+                    }
+                }
+            }
+            MogiState::Position => {
+                let position = observations.iter().find_map(|o| match o.kind {
+                    ObservationKind::Position(p) if o.score >= POSITION_SCORE => Some(p),
+                    _ => None,
+                });
+                if let Some(position) = position {
+                    log::info!("position: {position}");
+                    mogi_result.set_current_position(position);
+                    self.machine
+                        .transition(MogiState::CaptureTotalScores, Trigger::Detected, now);
+                    return Some(Screenshot::Race);
+                }
+            }
+            MogiState::CaptureTotalScores => {
+                // 獲得点数が消えて総合順位の画面になってから保存する
+                if observations
+                    .iter()
+                    .any(|o| o.kind == ObservationKind::TotalScores)
+                {
+                    log::info!("capture total scores");
+                    self.machine
+                        .transition(MogiState::Course, Trigger::Detected, now);
+                    return Some(Screenshot::Total);
+                }
+            }
+        }
+        None
+    }
+}
+
+// 一番確からしいコースを探す
+fn find_course(observations: &[Observation], min_score: f64) -> Option<&FoundCourse> {
+    observations
+        .iter()
+        .filter(|o| o.score >= min_score)
+        .filter_map(|o| match &o.kind {
+            ObservationKind::Course(found) => Some((found, o.score)),
+            _ => None,
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(found, _)| found)
+}
+
+fn has_results_table(observations: &[Observation]) -> bool {
+    observations
+        .iter()
+        .any(|o| o.kind == ObservationKind::ResultsTable && o.score >= RESULTS_TABLE_SCORE)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use crate::courses::{Console, Course};
+    use crate::race_result::Position;
+
+    use super::*;
+
+    fn frame(secs: u64) -> Frame {
+        Frame::new(
+            RgbImage::new(4, 4),
+            Duration::from_secs(secs),
+            secs,
+            "test".into(),
+        )
+    }
+
+    fn course(course: &Course) -> Observation {
+        let found = FoundCourse {
+            course: course.clone(),
+            alternatives: Vec::new(),
+            ocr_texts: vec!["DS ワリオスタジアム".to_string()],
+        };
+        Observation::new(ObservationKind::Course(found), 1.0)
+    }
+
+    fn results_table() -> Observation {
+        Observation::new(ObservationKind::ResultsTable, 1.0)
+    }
+
+    fn position(position: Position) -> Observation {
+        Observation::new(ObservationKind::Position(position), 1.0)
+    }
+
+    fn wario() -> Course {
+        Course::new("ワリオスタジアム".to_string(), Console::DS)
+    }
+
+    #[test]
+    fn test_race() {
+        let mut mogi_result = MogiResult::new();
+        let mut arbiter = Arbiter::new(MogiState::Course);
+
+        assert_eq!(arbiter.apply(&frame(0), &[], &mut mogi_result), None);
+        arbiter.apply(&frame(1), &[course(&wario())], &mut mogi_result);
+        assert_eq!(arbiter.state(), MogiState::RaceFinish);
+        assert_eq!(mogi_result.current_course(), &Some(wario()));
+
+        // 順位は続けて同じものが見えるまで記録しない
+        arbiter.apply(&frame(150), &[results_table()], &mut mogi_result);
+        assert_eq!(arbiter.state(), MogiState::Position);
+        let unstable = Observation::new(ObservationKind::Position(Position::Second), 0.5);
+        assert_eq!(
+            arbiter.apply(&frame(151), &[unstable], &mut mogi_result),
+            None
+        );
+        let screenshot = arbiter.apply(
+            &frame(152),
+            &[results_table(), position(Position::Second)],
+            &mut mogi_result,
+        );
+        assert_eq!(screenshot, Some(Screenshot::Race));
+        assert_eq!(arbiter.state(), MogiState::CaptureTotalScores);
+
+        // 総合順位の画面が出るまで待つ
+        assert_eq!(
+            arbiter.apply(&frame(160), &[results_table()], &mut mogi_result),
+            None
+        );
+        let total_scores = Observation::new(ObservationKind::TotalScores, 1.0);
+        assert_eq!(
+            arbiter.apply(&frame(161), &[total_scores], &mut mogi_result),
+            Some(Screenshot::Total)
+        );
+        assert_eq!(arbiter.state(), MogiState::Course);
+
+        let race = mogi_result.iter_races().next().unwrap();
+        assert_eq!(race.course(), Some(wario()));
+        assert_eq!(race.position(), Position::Second);
+        assert_eq!(race.ocr_texts(), ["DS ワリオスタジアム".to_string()]);
+    }
+
+    #[test]
+    fn test_missed_course() {
+        let mut mogi_result = MogiResult::new();
+        let mut arbiter = Arbiter::new(MogiState::Course);
+
+        // コースを探し始めたばかりの表は、前のレースの総合順位かもしれない
+        arbiter.apply(&frame(0), &[results_table()], &mut mogi_result);
+        assert_eq!(arbiter.state(), MogiState::Course);
+
+        // ロード画面を見逃しても、コースがわからないまま順位を記録する
+        arbiter.apply(&frame(90), &[results_table()], &mut mogi_result);
+        assert_eq!(arbiter.state(), MogiState::Position);
+        arbiter.apply(&frame(91), &[position(Position::Third)], &mut mogi_result);
+
+        let race = mogi_result.iter_races().next().unwrap();
+        assert_eq!(race.course(), None);
+        assert_eq!(race.position(), Position::Third);
+    }
+
+    #[test]
+    fn test_error_dialog() {
+        let mut mogi_result = MogiResult::new();
+        let mut arbiter = Arbiter::new(MogiState::Course);
+        arbiter.apply(&frame(0), &[course(&wario())], &mut mogi_result);
+
+        // 通信エラーになったレースは記録しない
+        let error = Observation::new(ObservationKind::ErrorDialog, 1.0);
+        arbiter.apply(&frame(60), &[error], &mut mogi_result);
+        assert_eq!(arbiter.state(), MogiState::Course);
+        assert_eq!(mogi_result.current_course(), &None);
+        assert_eq!(mogi_result.iter_races().len(), 0);
+    }
+}
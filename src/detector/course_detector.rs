@@ -1,15 +1,36 @@
-use crate::courses::get_course_by_words_with_nearest;
-use crate::detector::RaceFinishDetector;
+use crate::console_badge::detect_console;
+use crate::course_preview::{COURSE_PREVIEWS, PREVIEW_THRESHOLD};
+use crate::courses::get_course_by_words;
+use crate::courses::{rank_courses_by_words, Console, Course};
+use crate::frame::Frame;
+use crate::learned_aliases::LEARNED_ALIASES;
+use crate::ocr::{OcrPool, OcrTicket, Rect};
 use crate::size::{HEIGHT, WIDTH};
-use crate::{courses::get_course_by_words, mogi_result::MogiResult, word::Word};
+use crate::word::{Word, WordKind};
 use async_trait::async_trait;
-use image::Rgb;
 use image::{ImageBuffer, Luma};
 
-use super::{words_from_image_buffer, Detector};
+use super::{Detector, Observation, ObservationKind};
+
+// コース名は画面下部にあるので、その部分だけを認識する
+const COURSE_NAME_TOP: u32 = (950.0 / 1080.0 * HEIGHT as f64) as u32;
+const COURSE_NAME_REGION: Rect = Rect::new(
+    0,
+    COURSE_NAME_TOP,
+    WIDTH as u32,
+    HEIGHT as u32 - COURSE_NAME_TOP,
+);
+// 完全に一致しなかった場合、一番似ている候補の点数がこれ以上ならそのコースとして報告する
+const MIN_CANDIDATE_SCORE: f64 = 0.7;
+// 1番目と2番目の候補の点数の差がこれ未満なら、どちらか判断がつかない
+const AMBIGUITY_MARGIN: f64 = 0.1;
+// 推測した場合に、GUIで選び直せるようにしておく候補の数
+const ALTERNATIVES: usize = 3;
 
 pub struct CourseDetector {
     on_results_vec: Vec<bool>,
+    // 認識中のコース名
+    pending: Option<OcrTicket>,
 }
 
 impl CourseDetector {
@@ -17,6 +38,7 @@ impl CourseDetector {
         log::info!("CourseDetector");
         CourseDetector {
             on_results_vec: Vec::new(),
+            pending: None,
         }
     }
 
@@ -42,68 +64,180 @@ impl CourseDetector {
 
 #[async_trait]
 impl Detector for CourseDetector {
-    async fn detect(
-        mut self: Box<Self>,
-        buffer: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-        mogi_result: &mut MogiResult,
-    ) -> anyhow::Result<Box<dyn Detector + Send + Sync>> {
+    async fn observe(
+        &mut self,
+        frame: &Frame,
+        ocr: &OcrPool,
+    ) -> anyhow::Result<Option<Observation>> {
+        let buffer = frame.image();
         let input = image::DynamicImage::ImageRgb8(buffer.clone());
         let input = input.to_luma32f();
         self.eval_on_course_wait_room(&input);
         if !self.is_on_course_wait_room() {
-            return Ok(self);
+            // 黒帯が消えたら、認識中のものは使わないのでキャンセルする
+            self.pending = None;
+            return Ok(None);
         }
 
-        let words = match words_from_image_buffer(
-            buffer,
-            buffer.width().try_into()?,
-            buffer.height().try_into()?,
-        )
-        .await
-        {
-            Ok(w) => w,
-            Err(e) => {
+        let preview = COURSE_PREVIEWS.lock().unwrap().identify(buffer);
+        if let Some((course, score)) = preview {
+            log::trace!("course preview: {course} ({score:.3})");
+            if score >= PREVIEW_THRESHOLD {
+                log::info!("course with preview: {course} ({score:.3})");
+                let found = FoundCourse {
+                    course,
+                    alternatives: Vec::new(),
+                    ocr_texts: Vec::new(),
+                };
+                return Ok(Some(Observation::new(
+                    ObservationKind::Course(found),
+                    score as f64,
+                )));
+            }
+        }
+
+        let Some(ticket) = self.pending.take() else {
+            self.pending = Some(ocr.submit(frame, COURSE_NAME_REGION));
+            return Ok(None);
+        };
+        let words = match ticket.try_result() {
+            Some(Ok(w)) => w,
+            Some(Err(e)) => {
                 log::error!("Error: {:?}", e);
-                return Ok(self);
+                return Ok(None);
+            }
+            None => {
+                self.pending = Some(ticket);
+                return Ok(None);
             }
         };
         if !words.is_empty() {
             log::trace!("words: {:?}", &words);
         }
-        let for_course_texts = words
-            .into_iter()
-            .filter(filter_for_course_texts)
-            .collect::<Vec<Word>>();
-
-        if !for_course_texts.is_empty() {
-            log::trace!("for_course_texts: {:?}", &for_course_texts);
-        }
-
-        let course = get_course_by_words(&for_course_texts);
-        if let Some(course) = course {
-            log::info!("course: {course}");
-            mogi_result.set_current_course(course);
-            return Ok(Box::new(RaceFinishDetector::new()));
-        } else {
-            let course = get_course_by_words_with_nearest(&for_course_texts, 3);
-            if let Some(course) = course {
-                log::info!("course with nearest: {course}");
-                mogi_result.set_current_course(course);
-                return Ok(Box::new(RaceFinishDetector::new()));
+        // シリーズ名はロード画面の間は変わらないので、今のフレームから調べる
+        let console = detect_console(buffer);
+        let Some((found, score)) = course_from_words(&words, console) else {
+            return Ok(None);
+        };
+        // 次からはOCRなしでわかるように、確実な場合だけプレビューを覚えておく
+        // シリーズ名の画像の判定を間違えると別のコースとして覚えてしまうので、OCRの文字だけで一致した場合に限る
+        let mut previews = COURSE_PREVIEWS.lock().unwrap();
+        if found.alternatives.is_empty()
+            && is_exact_without_badge(&words, &found.course)
+            && !previews.contains(&found.course)
+        {
+            if let Err(e) = previews.save_reference(buffer, &found.course) {
+                log::error!("failed to save course preview: {:?}", e);
             }
         }
+        Ok(Some(Observation::new(
+            ObservationKind::Course(found),
+            score,
+        )))
+    }
+}
+
+/// ロード画面から見つけたコース
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundCourse {
+    pub course: Course,
+    // 推測した場合の他の候補。完全に一致した場合は空
+    pub alternatives: Vec<Course>,
+    // 手動で直された場合に覚えておく、OCRの文字列
+    pub ocr_texts: Vec<String>,
+}
+
+// OCRの結果からコースを探す。完全に一致するものがなければ似ている候補から選ぶ
+// 完全に一致した場合の点数は1.0
+fn course_from_words(words: &[Word], console: Option<Console>) -> Option<(FoundCourse, f64)> {
+    let for_course_texts = words
+        .iter()
+        .filter(|w| filter_for_course_texts(w))
+        .cloned()
+        .collect::<Vec<Word>>();
+
+    if !for_course_texts.is_empty() {
+        log::trace!("for_course_texts: {:?}", &for_course_texts);
+    }
+    let ocr_texts = ocr_texts(&for_course_texts);
+
+    if let Some(course) = get_course_by_words(&for_course_texts, console) {
+        log::info!("course: {course}");
+        let found = FoundCourse {
+            course,
+            alternatives: Vec::new(),
+            ocr_texts,
+        };
+        return Some((found, 1.0));
+    }
 
-        Ok(self)
+    // 前に手動で直された読み間違いは、直されたコースにする
+    // コース名として正しく読めた場合は、シリーズ名の画像の判定を優先する
+    let learned = {
+        let aliases = LEARNED_ALIASES.lock().unwrap();
+        for_course_texts
+            .iter()
+            .find_map(|w| aliases.get(&w.text).cloned())
+    };
+    if let Some(course) = learned {
+        log::info!("course with learned alias: {course}");
+        let found = FoundCourse {
+            course,
+            alternatives: Vec::new(),
+            ocr_texts,
+        };
+        return Some((found, 1.0));
     }
+
+    let mut ranked = rank_courses_by_words(&for_course_texts, console).into_iter();
+    let (course, score) = ranked.next()?;
+    if score < MIN_CANDIDATE_SCORE {
+        return None;
+    }
+    let alternatives = ranked.take(ALTERNATIVES).collect::<Vec<(Course, f64)>>();
+    let margin = score - alternatives.first().map_or(0.0, |(_, s)| *s);
+    if margin < AMBIGUITY_MARGIN {
+        log::warn!("ambiguous course: {course} ({score:.2}), candidates: {alternatives:?}");
+    } else {
+        log::info!("course with nearest: {course} ({score:.2})");
+    }
+    let found = FoundCourse {
+        course,
+        alternatives: alternatives.into_iter().map(|(c, _)| c).collect(),
+        ocr_texts,
+    };
+    Some((found, score))
 }
 
-fn filter_for_course_texts(word: &Word) -> bool {
-    // コース名は画面下部にあるので、上部の文字は除外
-    let lower: f64 = 950.0 / 1080.0 * (HEIGHT as f64);
-    if word.y < lower {
-        return false;
+// シリーズ名の画像を使わずに、OCRの文字だけで `course` に完全に一致するか
+fn is_exact_without_badge(words: &[Word], course: &Course) -> bool {
+    let for_course_texts = words
+        .iter()
+        .filter(|w| filter_for_course_texts(w))
+        .cloned()
+        .collect::<Vec<Word>>();
+    get_course_by_words(&for_course_texts, None).as_ref() == Some(course)
+}
+
+// 覚えておく文字列。1文字ずつの単語では短すぎるので、行があれば行を、なければ一番長い単語を使う
+fn ocr_texts(words: &[Word]) -> Vec<String> {
+    let lines = words
+        .iter()
+        .filter(|w| w.kind == WordKind::Line)
+        .map(|w| w.text.clone())
+        .collect::<Vec<String>>();
+    if !lines.is_empty() {
+        return lines;
     }
+    words
+        .iter()
+        .max_by_key(|w| w.text.chars().count())
+        .map(|w| vec![w.text.clone()])
+        .unwrap_or_default()
+}
 
+// 画面下部の文字だけが渡される
+fn filter_for_course_texts(word: &Word) -> bool {
     // 6文字以上の場合、コース名っぽいので通す
     if word.text.chars().count() >= 6 {
         return true;
@@ -121,3 +255,100 @@ fn filter_for_course_texts(word: &Word) -> bool {
         || lower_text.contains("3ds")
         || lower_text.contains("tour")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::RgbImage;
+
+    use crate::ocr::{FixtureEntry, FixtureOcr};
+
+    use super::*;
+
+    // Windows.Media.Ocrは日本語を1文字ずつの単語に分けて、最後に行をつなげたものを返す
+    fn winrt_like_words(line: &[&str]) -> Vec<Word> {
+        let mut words = line
+            .iter()
+            .map(|t| Word::new(t.to_string(), 0.0, 0.0, 30.0, 30.0))
+            .collect::<Vec<Word>>();
+        words.push(Word {
+            kind: WordKind::Line,
+            ..Word::new(line.concat(), 0.0, 0.0, 30.0, 300.0)
+        });
+        words
+    }
+
+    async fn course_from_fixture(line: &[&str]) -> anyhow::Result<Option<Course>> {
+        let ocr = FixtureOcr::new(vec![FixtureEntry {
+            index: 0,
+            hash: String::new(),
+            words: winrt_like_words(line),
+        }]);
+        let pool = OcrPool::new(Box::new(ocr), 1, Duration::from_secs(5));
+        // 黒帯が出ているロード画面
+        let image = RgbImage::new(WIDTH as u32, HEIGHT as u32);
+        let frame = Frame::new(image, Duration::ZERO, 0, "test".into());
+        let words = pool.submit(&frame, COURSE_NAME_REGION).result().await?;
+        Ok(course_from_words(&words, None).map(|(found, _)| found.course))
+    }
+
+    // 一致した後の RaceFinishDetector はGPUが必要なので、コースを探すところまでを確かめる
+    #[tokio::test]
+    async fn test_course_from_fixture() -> anyhow::Result<()> {
+        assert_eq!(
+            course_from_fixture(&["DS", "ワ", "リ", "オ", "ス", "タ", "ジ", "ア", "ム"]).await?,
+            Some(Course::new("ワリオスタジアム".to_string(), Console::DS))
+        );
+        // 長音が漢字の「一」と誤認識されていても、近いコースを探す
+        assert_eq!(
+            course_from_fixture(&["GC", "ヨ", "ッ", "シ", "一", "サ", "ー", "キ", "ッ", "ト"])
+                .await?,
+            Some(Course::new("ヨッシーサーキット".to_string(), Console::GC))
+        );
+        // コース名以外の文字は無視する
+        assert_eq!(
+            course_from_fixture(&[
+                "し", "ば", "ら", "く", "お", "待", "ち", "く", "だ", "さ", "い"
+            ])
+            .await?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_exact_without_badge() {
+        let wario = Course::new("ワリオスタジアム".to_string(), Console::DS);
+        // シリーズ名の画像だけでDSとわかった場合は、プレビューを覚えない
+        let words = vec![Word::new(
+            "ワリオスタジアム".to_string(),
+            0.0,
+            0.0,
+            30.0,
+            300.0,
+        )];
+        let (found, _) = course_from_words(&words, Some(Console::DS)).unwrap();
+        assert_eq!(found.course, wario);
+        assert!(!is_exact_without_badge(&words, &found.course));
+
+        let words = vec![
+            Word::new("DS".to_string(), 0.0, 0.0, 30.0, 30.0),
+            Word::new("ワリオスタジアム".to_string(), 0.0, 0.0, 30.0, 300.0),
+        ];
+        assert!(is_exact_without_badge(&words, &wario));
+    }
+
+    #[test]
+    fn test_ocr_texts() {
+        // 行があれば行だけを覚える
+        let words = winrt_like_words(&["GC", "ヨ", "ッ", "シ", "一"]);
+        assert_eq!(ocr_texts(&words), vec!["GCヨッシ一".to_string()]);
+        let words = vec![
+            Word::new("DS".to_string(), 0.0, 0.0, 30.0, 30.0),
+            Word::new("ワリオスタシアム".to_string(), 0.0, 0.0, 30.0, 300.0),
+        ];
+        assert_eq!(ocr_texts(&words), vec!["ワリオスタシアム".to_string()]);
+        assert!(ocr_texts(&[]).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+
+use crate::frame::Frame;
+use crate::normalize::normalize;
+use crate::ocr::{OcrPool, OcrTicket, Rect};
+use crate::size::{HEIGHT, WIDTH};
+use crate::word::Word;
+
+use super::{Detector, MogiState, Observation, ObservationKind};
+
+// 通信エラーのダイアログは画面中央に出るので、その部分だけを認識する
+const ERROR_DIALOG_REGION: Rect = Rect::new(
+    WIDTH as u32 / 4,
+    HEIGHT as u32 / 4,
+    WIDTH as u32 / 2,
+    HEIGHT as u32 / 2,
+);
+
+/// 通信エラーのダイアログを探す
+/// OCRの結果は次のフレーム以降で受け取るので、認識中も他のDetectorは判定を続けられる
+/// 毎フレームOCRすると重いので、エラーで止まりやすいレース中とリザルト画面でだけ動かす
+#[derive(Default)]
+pub struct ErrorDialogDetector {
+    pending: Option<OcrTicket>,
+}
+
+impl ErrorDialogDetector {
+    pub fn new() -> ErrorDialogDetector {
+        log::info!("ErrorDialogDetector");
+        ErrorDialogDetector::default()
+    }
+}
+
+#[async_trait]
+impl Detector for ErrorDialogDetector {
+    async fn observe(
+        &mut self,
+        frame: &Frame,
+        ocr: &OcrPool,
+    ) -> anyhow::Result<Option<Observation>> {
+        let mut observation = None;
+        if let Some(result) = self.pending.as_ref().and_then(|t| t.try_result()) {
+            self.pending = None;
+            match result {
+                Ok(words) => {
+                    if is_error_dialog(words) {
+                        log::warn!("エラーが発生しました");
+                        observation = Some(Observation::new(ObservationKind::ErrorDialog, 1.0));
+                    }
+                }
+                Err(e) => log::error!("Error: {:?}", e),
+            }
+        }
+        if self.pending.is_none() {
+            self.pending = Some(ocr.submit(frame, ERROR_DIALOG_REGION));
+        }
+        Ok(observation)
+    }
+
+    fn is_active(&self, state: MogiState) -> bool {
+        matches!(state, MogiState::RaceFinish | MogiState::Position)
+    }
+
+    fn deactivate(&mut self) {
+        // 前の状態で出した認識の結果で、次のレースのコースを消さないようにする
+        self.pending = None;
+    }
+}
+
+fn is_error_dialog(words: Vec<Word>) -> bool {
+    let normalized_words = words
+        .into_iter()
+        .filter(|w| w.text.len() >= 2)
+        .map(|w| normalize(&w.text.replace(' ', "")))
+        .collect::<Vec<String>>();
+
+    let mut error_count = 0;
+    for word in &normalized_words {
+        for error_word in &["エラー", "通信", "はっせい", "しました"] {
+            if word.contains(&normalize(error_word)) {
+                error_count += 1;
+            }
+            if error_count == 4 {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::RgbImage;
+
+    use crate::ocr::{FixtureEntry, FixtureOcr};
+
+    use super::*;
+
+    fn fixture(texts: &[&str]) -> FixtureOcr {
+        FixtureOcr::new(vec![FixtureEntry {
+            index: 0,
+            hash: String::new(),
+            words: texts
+                .iter()
+                .map(|t| Word::new(t.to_string(), 0.0, 0.0, 30.0, 100.0))
+                .collect(),
+        }])
+    }
+
+    async fn observe_with(texts: &[&str]) -> anyhow::Result<Option<Observation>> {
+        let pool = OcrPool::new(Box::new(fixture(texts)), 1, Duration::from_secs(5));
+        let frame = Frame::new(
+            RgbImage::new(WIDTH as u32, HEIGHT as u32),
+            Duration::ZERO,
+            0,
+            "test".into(),
+        );
+        let mut detector = ErrorDialogDetector::new();
+        // 最初のフレームでは認識を要求するだけで、結果は待たない
+        assert_eq!(detector.observe(&frame, &pool).await?, None);
+        detector.pending.clone().unwrap().result().await?;
+        detector.observe(&frame, &pool).await
+    }
+
+    #[tokio::test]
+    async fn test_error_dialog_detector() -> anyhow::Result<()> {
+        let observation = observe_with(&[
+            "通",
+            "信",
+            "エラーがはっせいしました。",
+            "通信エラーがはっせいしました。",
+            "エラーコード:2618-0516",
+        ])
+        .await?;
+        assert_eq!(
+            observation.map(|o| o.kind),
+            Some(ObservationKind::ErrorDialog)
+        );
+
+        // 通信中の表示はエラーではない
+        let observation = observe_with(&["通信中", "しばらくお待ちください"]).await?;
+        assert_eq!(observation, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_active_states() {
+        let pool = OcrPool::new(Box::new(fixture(&[])), 1, Duration::from_secs(5));
+        let frame = Frame::new(
+            RgbImage::new(WIDTH as u32, HEIGHT as u32),
+            Duration::ZERO,
+            0,
+            "test".into(),
+        );
+        let mut detector = ErrorDialogDetector::new();
+        assert!(!detector.is_active(MogiState::Course));
+        assert!(detector.is_active(MogiState::RaceFinish));
+        assert!(detector.is_active(MogiState::Position));
+        assert!(!detector.is_active(MogiState::CaptureTotalScores));
+
+        detector.pending = Some(pool.submit(&frame, ERROR_DIALOG_REGION));
+        detector.deactivate();
+        assert!(detector.pending.is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use std::ops::RangeInclusive;
+
+use image::{ImageBuffer, Luma};
+
+/// マスク付きの二乗誤差でテンプレートを探す。GPUが使えない環境向け
+/// 画面全体ではなく、指定した範囲だけを探す
+pub struct MaskedTemplate {
+    width: u32,
+    height: u32,
+    // マスクが0でない画素の (x, y, 値, 重み)
+    pixels: Vec<(u32, u32, f32, f32)>,
+    weight_sum: f32,
+}
+
+impl MaskedTemplate {
+    pub fn new(
+        template: &ImageBuffer<Luma<f32>, Vec<f32>>,
+        mask: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    ) -> MaskedTemplate {
+        let pixels = template
+            .enumerate_pixels()
+            .map(|(x, y, p)| (x, y, p.0[0], mask.get_pixel(x, y).0[0]))
+            .filter(|(_, _, _, w)| *w > 0.0)
+            .collect::<Vec<_>>();
+        let weight_sum = pixels.iter().map(|(_, _, _, w)| w).sum();
+        MaskedTemplate {
+            width: template.width(),
+            height: template.height(),
+            pixels,
+            weight_sum,
+        }
+    }
+
+    /// 左上を `xs`、`ys` の範囲に置いたときに、重み付きの二乗誤差の平均が一番小さい位置とその値を返す
+    /// テンプレートが画面からはみ出す位置は探さない
+    pub fn best_match(
+        &self,
+        input: &ImageBuffer<Luma<f32>, Vec<f32>>,
+        xs: RangeInclusive<u32>,
+        ys: RangeInclusive<u32>,
+    ) -> Option<((u32, u32), f32)> {
+        if self.weight_sum <= 0.0 || input.width() < self.width || input.height() < self.height {
+            return None;
+        }
+        let max_x = (*xs.end()).min(input.width() - self.width);
+        let max_y = (*ys.end()).min(input.height() - self.height);
+        let mut best: Option<((u32, u32), f32)> = None;
+        for top in *ys.start()..=max_y {
+            for left in *xs.start()..=max_x {
+                let limit = best.map_or(f32::INFINITY, |(_, s)| s * self.weight_sum);
+                let mut sum = 0.0f32;
+                for (x, y, t, w) in &self.pixels {
+                    let d = input.get_pixel(left + x, top + y).0[0] - t;
+                    sum += w * d * d;
+                    // これより良くならないので打ち切る
+                    if sum >= limit {
+                        break;
+                    }
+                }
+                if sum < limit {
+                    best = Some(((left, top), sum / self.weight_sum));
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type LumaImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+    // 縦縞のテンプレートと、左半分だけを見るマスク
+    fn template() -> (LumaImage, LumaImage) {
+        let template =
+            ImageBuffer::from_fn(8, 20, |x, _| Luma([if x % 3 == 0 { 1.0 } else { 0.0 }]));
+        let mask = ImageBuffer::from_fn(8, 20, |x, _| Luma([if x < 4 { 1.0 } else { 0.0 }]));
+        (template, mask)
+    }
+
+    #[test]
+    fn test_best_match() {
+        let (template, mask) = template();
+        let matcher = MaskedTemplate::new(&template, &mask);
+
+        let mut input = ImageBuffer::from_pixel(100, 60, Luma([0.5f32]));
+        for (x, y, p) in template.enumerate_pixels() {
+            // マスクの外は違っていても気にしない
+            let value = if x < 4 { p.0[0] } else { 0.3 };
+            input.put_pixel(20 + x, 30 + y, Luma([value]));
+        }
+        let (location, score) = matcher.best_match(&input, 10..=30, 25..=35).unwrap();
+        assert_eq!(location, (20, 30));
+        assert!(score < 1e-6);
+
+        // 範囲の外にあるものは見つけない
+        let (location, score) = matcher.best_match(&input, 40..=60, 0..=10).unwrap();
+        assert_ne!(location, (20, 30));
+        assert!(score > 0.1);
+
+        // はみ出す範囲は画面の端までにする
+        let (location, _) = matcher.best_match(&input, 90..=99, 30..=59).unwrap();
+        assert!(location.0 <= 92 && location.1 <= 40);
+        assert_eq!(matcher.best_match(&input, 95..=99, 0..=10), None);
+    }
+}
@@ -1,55 +1,222 @@
 use async_trait::async_trait;
-use image::ImageBuffer;
-use image::Rgb;
 
-use crate::mogi_result::MogiResult;
-use crate::word::normalize_japanese_characters;
-use crate::word::words_from_image_buffer;
+use crate::frame::Frame;
+use crate::ocr::OcrPool;
+use crate::race_result::Position;
 
-mod capture_total_scores_detector;
+mod arbiter;
 mod course_detector;
+mod error_dialog_detector;
+mod masked_match;
 mod position_detector;
 mod race_finish_detector;
+mod results_table_reader;
+mod state_machine;
+mod total_scores_detector;
 
-pub use capture_total_scores_detector::CaptureTotalScoresDetector;
-pub use course_detector::CourseDetector;
+pub use arbiter::{Arbiter, Screenshot};
+pub use course_detector::{CourseDetector, FoundCourse};
+pub use error_dialog_detector::ErrorDialogDetector;
 pub use position_detector::PositionDetector;
 pub use race_finish_detector::RaceFinishDetector;
+pub use results_table_reader::ResultsTableReader;
+pub use state_machine::MogiState;
+pub use total_scores_detector::TotalScoresDetector;
+
+/// Detectorが見つけたもの
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObservationKind {
+    // ロード画面でコースがわかった
+    Course(FoundCourse),
+    // リザルト画面の表が出ている
+    ResultsTable,
+    // リザルト画面の自分の順位
+    Position(Position),
+    // 通信エラーのダイアログが出ている
+    ErrorDialog,
+    // 総合順位の画面が出ている
+    TotalScores,
+}
+
+/// 見つけたものと、その確からしさ(0.0から1.0)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub kind: ObservationKind,
+    pub score: f64,
+}
+
+impl Observation {
+    pub fn new(kind: ObservationKind, score: f64) -> Observation {
+        Observation { kind, score }
+    }
+}
 
 #[async_trait]
 pub trait Detector {
-    async fn detect(
-        self: Box<Self>,
-        buffer: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-        mogi_result: &mut MogiResult,
-    ) -> anyhow::Result<Box<dyn Detector + Send + Sync>>;
-
-    async fn detect_error(
-        &self,
-        buffer: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-        mogi_result: &mut MogiResult,
-    ) -> anyhow::Result<bool> {
-        let words =
-            words_from_image_buffer(buffer, buffer.width() as _, buffer.height() as _).await?;
-        let normalized_words = words
-            .into_iter()
-            .filter(|w| w.text.len() >= 2)
-            .map(|w| normalize_japanese_characters(w.text.replace(' ', "")))
-            .collect::<Vec<String>>();
-
-        let mut error_count = 0;
-        for word in &normalized_words {
-            for error_word in &["エラー", "通信", "はっせい", "しました"] {
-                if word.contains(&normalize_japanese_characters(error_word.to_string())) {
-                    error_count += 1;
-                }
-                if error_count == 4 {
-                    log::warn!("エラーが発生しました");
-                    mogi_result.reset_current_course();
-                    return Ok(true);
-                }
+    /// フレームから見つけたものを返す。MogiResultは変えずに、Arbiterがまとめて判断する
+    async fn observe(
+        &mut self,
+        frame: &Frame,
+        ocr: &OcrPool,
+    ) -> anyhow::Result<Option<Observation>>;
+
+    /// `state` のときに動かすか。OCRを使うDetectorは必要な状態だけに絞る
+    fn is_active(&self, _state: MogiState) -> bool {
+        true
+    }
+
+    /// 動かさない状態になったときに呼ばれる。古い認識の結果を捨てる
+    fn deactivate(&mut self) {}
+}
+
+/// すべてのDetectorを毎フレーム動かす
+/// 前の画面を見逃しても、次の画面は見つけられる
+pub struct DetectorSet {
+    detectors: Vec<Box<dyn Detector + Send + Sync>>,
+}
+
+impl DetectorSet {
+    pub fn new() -> DetectorSet {
+        DetectorSet::with_detectors(vec![
+            Box::new(CourseDetector::new()),
+            Box::new(RaceFinishDetector::new()),
+            Box::new(PositionDetector::new()),
+            Box::new(ErrorDialogDetector::new()),
+            Box::new(TotalScoresDetector::new()),
+        ])
+    }
+
+    fn with_detectors(detectors: Vec<Box<dyn Detector + Send + Sync>>) -> DetectorSet {
+        DetectorSet { detectors }
+    }
+
+    pub async fn observe(
+        &mut self,
+        frame: &Frame,
+        ocr: &OcrPool,
+        state: MogiState,
+    ) -> anyhow::Result<Vec<Observation>> {
+        let mut observations = Vec::new();
+        for detector in self.detectors.iter_mut() {
+            if !detector.is_active(state) {
+                detector.deactivate();
+                continue;
+            }
+            if let Some(observation) = detector.observe(frame, ocr).await? {
+                log::trace!("observation: {:?}", observation);
+                observations.push(observation);
             }
         }
-        Ok(false)
+        Ok(observations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use std::time::Duration;
+
+    use image::RgbImage;
+
+    use crate::clock::{Clock, MockClock};
+    use crate::courses::{Console, Course};
+    use crate::mogi_result::MogiResult;
+    use crate::ocr::FixtureOcr;
+
+    use super::*;
+
+    // 決めた時間の間だけ、決めたものを見つけるDetector
+    struct ScriptedDetector {
+        script: Vec<(Range<Duration>, ObservationKind)>,
+    }
+
+    #[async_trait]
+    impl Detector for ScriptedDetector {
+        async fn observe(
+            &mut self,
+            frame: &Frame,
+            _ocr: &OcrPool,
+        ) -> anyhow::Result<Option<Observation>> {
+            Ok(self
+                .script
+                .iter()
+                .find(|(range, _)| range.contains(&frame.timestamp()))
+                .map(|(_, kind)| Observation::new(kind.clone(), 1.0)))
+        }
+    }
+
+    fn secs(range: Range<u64>) -> Range<Duration> {
+        Duration::from_secs(range.start)..Duration::from_secs(range.end)
+    }
+
+    fn wario() -> Course {
+        Course::new("ワリオスタジアム".to_string(), Console::DS)
+    }
+
+    // 1レース分の画面を `frame_rate` で流し、保存したスクリーンショットとその時刻を返す
+    // 時計を手で進めるので、実時間を待たずに何倍もの速さで流れる
+    async fn run_race(
+        frame_rate: f64,
+    ) -> anyhow::Result<(MogiResult, Vec<(Screenshot, Duration)>)> {
+        let found = FoundCourse {
+            course: wario(),
+            alternatives: Vec::new(),
+            ocr_texts: Vec::new(),
+        };
+        let mut detectors = DetectorSet::with_detectors(vec![
+            Box::new(ScriptedDetector {
+                script: vec![(secs(10..14), ObservationKind::Course(found))],
+            }),
+            Box::new(ScriptedDetector {
+                script: vec![(secs(150..160), ObservationKind::ResultsTable)],
+            }),
+            Box::new(ScriptedDetector {
+                script: vec![(secs(151..160), ObservationKind::Position(Position::Second))],
+            }),
+            Box::new(ScriptedDetector {
+                script: vec![(secs(163..166), ObservationKind::TotalScores)],
+            }),
+        ]);
+        let ocr = OcrPool::new(
+            Box::new(FixtureOcr::new(Vec::new())),
+            1,
+            Duration::from_secs(5),
+        );
+        let clock = MockClock::default();
+        let mut arbiter = Arbiter::new(MogiState::Course);
+        let mut mogi_result = MogiResult::new();
+        let mut screenshots = Vec::new();
+        let mut sequence = 0;
+        while clock.now() < Duration::from_secs(200) {
+            let frame = Frame::new(RgbImage::new(4, 4), clock.now(), sequence, "test".into());
+            let observations = detectors.observe(&frame, &ocr, arbiter.state()).await?;
+            if let Some(screenshot) = arbiter.apply(&frame, &observations, &mut mogi_result) {
+                screenshots.push((screenshot, frame.timestamp()));
+            }
+            clock.advance(Duration::from_secs_f64(1.0 / frame_rate));
+            sequence += 1;
+        }
+        Ok((mogi_result, screenshots))
+    }
+
+    #[tokio::test]
+    async fn test_race_with_mock_clock() -> anyhow::Result<()> {
+        let started = std::time::Instant::now();
+        for frame_rate in [5.0, 30.0] {
+            let (mogi_result, screenshots) = run_race(frame_rate).await?;
+            let races = mogi_result.iter_races().collect::<Vec<_>>();
+            assert_eq!(races.len(), 1);
+            assert_eq!(races[0].course(), Some(wario()));
+            assert_eq!(races[0].position(), Position::Second);
+
+            // fpsによらず、同じ画面でスクリーンショットを保存する
+            let kinds = screenshots.iter().map(|(s, _)| *s).collect::<Vec<_>>();
+            assert_eq!(kinds, vec![Screenshot::Race, Screenshot::Total]);
+            assert!(secs(151..152).contains(&screenshots[0].1));
+            assert!(secs(163..164).contains(&screenshots[1].1));
+        }
+        // 200秒分のフレームを実時間よりずっと速く流している
+        assert!(started.elapsed() < Duration::from_secs(20));
+        Ok(())
     }
 }
@@ -1,14 +1,14 @@
 use async_trait::async_trait;
-use image::{ImageBuffer, Luma, Pixel, Rgb};
+use image::{ImageBuffer, Luma, Pixel};
 use template_matching::{find_extremes, MatchTemplateMethod, TemplateMatcher};
 
-use crate::{
-    detector::{CourseDetector, PositionDetector},
-    mogi_result::MogiResult,
-    size::WIDTH,
-};
+use crate::{frame::Frame, ocr::OcrPool, size::WIDTH};
 
-use super::Detector;
+use super::masked_match::MaskedTemplate;
+use super::{Detector, Observation, ObservationKind};
+
+// CPUで探す場合、重み付きの二乗誤差の平均がこれ未満ならリザルト画面の表とする
+const MAX_CPU_RESULTS_ERROR: f32 = 0.02;
 
 // based 1280 x 720
 const FLAG_CHECK_PATTERN: [(u32, u32); 9] = [
@@ -54,10 +54,17 @@ pub struct RaceFinishDetector {
     race_kind: RaceKind,
     results_image: ImageBuffer<Luma<f32>, Vec<f32>>,
     results_mask_image: ImageBuffer<Luma<f32>, Vec<f32>>,
-    results_matcher: TemplateMatcher,
+    results_matcher: ResultsMatcher,
     on_results_vec: Vec<bool>,
 }
 
+enum ResultsMatcher {
+    // GPUで画面全体を探す
+    Gpu(TemplateMatcher),
+    // GPUが使えない環境向け。受け入れる位置の周りだけをCPUで探す
+    Cpu(MaskedTemplate),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RaceKind {
     Internet,
@@ -83,23 +90,27 @@ impl RaceFinishDetector {
             dx12_shader_compiler: Default::default(),
         });
 
+        let results_matcher = if instance
+            .enumerate_adapters(wgpu::Backends::VULKAN)
+            .next()
+            .is_some()
+        {
+            ResultsMatcher::Gpu(TemplateMatcher::new_from_instance(instance))
+        } else {
+            log::warn!("no GPU adapter found, match results template on CPU");
+            ResultsMatcher::Cpu(MaskedTemplate::new(&results_image, &results_mask_image))
+        };
+
         RaceFinishDetector {
             race_kind: RaceKind::Internet,
             results_image,
             results_mask_image,
-            results_matcher: TemplateMatcher::new_from_instance(instance),
+            results_matcher,
             on_results_vec: Vec::new(),
         }
     }
 
     fn eval_on_result_with_match_template(&mut self, input: &ImageBuffer<Luma<f32>, Vec<f32>>) {
-        self.results_matcher.match_template_mask(
-            input,
-            &self.results_image,
-            &self.results_mask_image,
-            MatchTemplateMethod::SumOfSquaredDifferences,
-        );
-        let results = self.results_matcher.wait_for_result();
         let location_offset_x_min: u32 = match self.race_kind {
             RaceKind::Internet => 555_u32,
             RaceKind::Local => 595_u32,
@@ -109,22 +120,33 @@ impl RaceFinishDetector {
             RaceKind::Internet => 568_u32,
             RaceKind::Local => 605_u32,
         };
-        if let Some(results) = results {
-            let extremes = find_extremes(&results);
-            log::trace!("results: {:?}", extremes.max_value_location);
-            if extremes.max_value_location.0 >= location_offset_x_min
-                && extremes.max_value_location.0 <= location_offset_x_max
-                && extremes.max_value_location.1 >= 42_u32
-                && extremes.max_value_location.1 <= 57_u32
-            {
-                self.on_results_vec.push(true);
-                if self.on_results_vec.len() > 4 {
-                    self.on_results_vec.remove(0);
-                    return;
-                }
+        let xs = location_offset_x_min..=location_offset_x_max;
+        let ys = 42_u32..=57_u32;
+        let on_result = match &mut self.results_matcher {
+            ResultsMatcher::Gpu(matcher) => {
+                matcher.match_template_mask(
+                    input,
+                    &self.results_image,
+                    &self.results_mask_image,
+                    MatchTemplateMethod::SumOfSquaredDifferences,
+                );
+                matcher.wait_for_result().is_some_and(|results| {
+                    let extremes = find_extremes(&results);
+                    log::trace!("results: {:?}", extremes.max_value_location);
+                    xs.contains(&extremes.max_value_location.0)
+                        && ys.contains(&extremes.max_value_location.1)
+                })
             }
-        }
-        self.on_results_vec.push(false);
+            ResultsMatcher::Cpu(template) => {
+                template
+                    .best_match(input, xs, ys)
+                    .is_some_and(|(location, error)| {
+                        log::trace!("results: {:?} ({error:.4})", location);
+                        error < MAX_CPU_RESULTS_ERROR
+                    })
+            }
+        };
+        self.on_results_vec.push(on_result);
         if self.on_results_vec.len() > 4 {
             self.on_results_vec.remove(0);
         }
@@ -134,19 +156,22 @@ impl RaceFinishDetector {
         // もし配列の中にtrueが3つ以上あれば、レース結果画面にいると判断する
         self.on_results_vec.iter().filter(|b| **b).count() >= 3
     }
+
+    // 直近のフレームのうち、リザルト画面だったものの割合
+    fn result_ratio(&self) -> f64 {
+        let count = self.on_results_vec.iter().filter(|b| **b).count();
+        count as f64 / self.on_results_vec.len().max(4) as f64
+    }
 }
 
 #[async_trait]
 impl Detector for RaceFinishDetector {
-    async fn detect(
-        mut self: Box<Self>,
-        buffer: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-        mogi_result: &mut MogiResult,
-    ) -> anyhow::Result<Box<dyn Detector + Send + Sync>> {
-        if self.detect_error(buffer, mogi_result).await? {
-            return Ok(Box::new(CourseDetector::new()));
-        }
-
+    async fn observe(
+        &mut self,
+        frame: &Frame,
+        _ocr: &OcrPool,
+    ) -> anyhow::Result<Option<Observation>> {
+        let buffer = frame.image();
         for (i, (x, y)) in FLAG_CHECK_PATTERN.into_iter().enumerate() {
             let pixel = buffer.get_pixel(x, y);
             let channels = pixel.channels();
@@ -156,11 +181,11 @@ impl Detector for RaceFinishDetector {
             if (i % 2) == 0 {
                 if r < 5 && g < 5 && b < 5 {
                     log::trace!("flag is on view");
-                    return Ok(self);
+                    return Ok(None);
                 }
             } else if r > 0xD0 && g > 0xD0 && b > 0xD0 {
                 log::trace!("flag is on view");
-                return Ok(self);
+                return Ok(None);
             }
         }
 
@@ -168,8 +193,11 @@ impl Detector for RaceFinishDetector {
         let input = input.to_luma32f();
         self.eval_on_result_with_match_template(&input);
         if self.is_on_result() {
-            return Ok(Box::new(PositionDetector::new()));
+            return Ok(Some(Observation::new(
+                ObservationKind::ResultsTable,
+                self.result_ratio(),
+            )));
         }
-        Ok(self)
+        Ok(None)
     }
 }
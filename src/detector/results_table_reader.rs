@@ -0,0 +1,382 @@
+use unicode_normalization::UnicodeNormalization;
+
+use crate::frame::Frame;
+use crate::mogi_result::MogiResult;
+use crate::ocr::{OcrPool, OcrTicket, Rect};
+use crate::race_result::{Position, ResultsRow};
+use crate::size::{HEIGHT, WIDTH};
+use crate::word::{Word, WordKind};
+
+use super::position_detector::{LINES, LINES_SAMPLE_OFFSET_Y, LINE_HEIGHT};
+
+// 行の左端。順位の数字とMiiの顔は読まなくてよいので、名前の少し左から
+const ROW_LEFT: u32 = (1100.0 / 1920.0 * WIDTH as f64) as u32;
+
+// `index` 行目の範囲。順位を調べる位置が行の真ん中あたりになる
+pub(super) fn row_region(index: usize) -> Rect {
+    let center = LINES_SAMPLE_OFFSET_Y + LINE_HEIGHT * index as f64;
+    let top = (center - LINE_HEIGHT / 2.0).max(0.0) as u32;
+    let height = (LINE_HEIGHT as u32).min(HEIGHT as u32 - top);
+    Rect::new(ROW_LEFT, top, WIDTH as u32 - ROW_LEFT, height)
+}
+
+// 続けてこの回数同じ表が読めたら、点数の加算が終わったとする
+const STABLE_READS: usize = 2;
+// これだけ読んでも落ち着かなければ、最後に読んだ表を残す
+const MAX_READS: usize = 6;
+
+/// リザルト画面の表を1行ずつOCRして、レースの結果に残す
+/// 順位が決まった直後は点数が加算されている途中なので、同じ表が続けて読めるまで読み直す
+/// 結果は次のフレーム以降で受け取るので、読み取り中も検出は止めない
+#[derive(Default)]
+pub struct ResultsTableReader {
+    // 書き込むレースの番号。読み取っていない間はNone
+    index: Option<usize>,
+    // 行ごとの認識
+    pending: Option<Vec<OcrTicket>>,
+    // 前回読んだ表と、続けて同じだった回数
+    last: Option<(Vec<ResultsRow>, usize)>,
+    reads: usize,
+}
+
+impl ResultsTableReader {
+    pub fn new() -> ResultsTableReader {
+        ResultsTableReader::default()
+    }
+
+    /// 次のフレームから、`index` 番目のレースの表として読み取り始める
+    pub fn start(&mut self, index: usize) {
+        if self.index.is_some() {
+            log::warn!("results table of the previous race is not read yet");
+        }
+        *self = ResultsTableReader {
+            index: Some(index),
+            ..ResultsTableReader::default()
+        };
+    }
+
+    /// 読み取り中なら、前の認識の結果を受け取って `frame` で次の認識を始める
+    pub fn observe(&mut self, frame: &Frame, ocr: &OcrPool, mogi_result: &mut MogiResult) {
+        if self.index.is_none() {
+            return;
+        }
+        if let Some(tickets) = &self.pending {
+            if tickets.iter().any(|t| t.try_result().is_none()) {
+                return;
+            }
+            let table = parse_table(tickets);
+            self.pending = None;
+            self.reads += 1;
+            log::debug!("results table (read {}): {:?}", self.reads, table);
+            // 認識できなかった行がある表は、同じ表が続いたかどうかの判定に使わない
+            let Some(table) = table else {
+                log::warn!("results table is not read completely");
+                if self.reads >= MAX_READS {
+                    self.finish(mogi_result);
+                } else {
+                    self.submit(frame, ocr);
+                }
+                return;
+            };
+            // 獲得点数が消えていたら、総合順位の画面に変わっている
+            if !table.is_empty() && table.iter().all(|r| r.points.is_none()) {
+                log::warn!("results table changed to total scores before it settled");
+                self.finish(mogi_result);
+                return;
+            }
+            let count = match &self.last {
+                Some((last, count)) if *last == table => count + 1,
+                _ => 1,
+            };
+            self.last = Some((table, count));
+            if count >= STABLE_READS || self.reads >= MAX_READS {
+                self.finish(mogi_result);
+                return;
+            }
+        }
+        self.submit(frame, ocr);
+    }
+
+    fn submit(&mut self, frame: &Frame, ocr: &OcrPool) {
+        let tickets = (0..LINES)
+            .map(|i| ocr.submit(frame, row_region(i)))
+            .collect();
+        self.pending = Some(tickets);
+    }
+
+    // 最後に読んだ表を書き込んで、読み取りをやめる
+    fn finish(&mut self, mogi_result: &mut MogiResult) {
+        if let (Some(index), Some((table, count))) = (self.index, self.last.take()) {
+            if count < STABLE_READS {
+                log::warn!("results table did not settle after {} reads", self.reads);
+            }
+            if !table.is_empty() {
+                log::info!("results table: {} rows", table.len());
+                mogi_result.set_table(index, table);
+            }
+        }
+        *self = ResultsTableReader::default();
+    }
+}
+
+// すべての行の認識から、読み取れた行を集める
+// 認識に失敗した行があればNoneを返す。キューがいっぱいで要求できなかった場合も含む
+fn parse_table(tickets: &[OcrTicket]) -> Option<Vec<ResultsRow>> {
+    let mut table = Vec::new();
+    for (i, ticket) in tickets.iter().enumerate() {
+        match ticket.try_result()? {
+            Ok(words) => table
+                .extend(Position::from_index(i).and_then(|position| parse_row(position, &words))),
+            Err(e) => {
+                log::error!("Error: {:?}", e);
+                return None;
+            }
+        }
+    }
+    Some(table)
+}
+
+// 1行分の単語から、名前・獲得点数・合計点を読み取る
+// 左から名前、+付きの獲得点数、合計点の順に並んでいる
+pub(super) fn parse_row(position: Position, words: &[Word]) -> Option<ResultsRow> {
+    let mut words = words
+        .iter()
+        .filter(|w| w.kind == WordKind::Word)
+        .collect::<Vec<&Word>>();
+    words.sort_by(|a, b| a.x.total_cmp(&b.x));
+
+    let mut name = String::new();
+    let mut points = None;
+    let mut total = None;
+    // +と数字が別の単語になることがある
+    let mut plus = false;
+    for word in words {
+        // 全角の数字や記号を半角にする
+        let text = word.text.nfkc().collect::<String>();
+        let text = text.trim();
+        if text == "+" {
+            plus = true;
+            continue;
+        }
+        if let Some(n) = text.strip_prefix('+').and_then(|t| t.parse::<u32>().ok()) {
+            points = Some(n);
+            continue;
+        }
+        if let Ok(n) = text.parse::<u32>() {
+            if plus {
+                points = Some(n);
+                plus = false;
+            } else if !name.is_empty() {
+                total = Some(n);
+            }
+            // 名前より左の数字は順位なので読まない
+            continue;
+        }
+        // 点数より右の文字は名前ではない
+        if points.is_none() && total.is_none() {
+            name.push_str(text);
+        }
+    }
+    if name.is_empty() {
+        return None;
+    }
+    Some(ResultsRow {
+        position,
+        name,
+        points,
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::courses::{Console, Course};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use crate::ocr::{create_ocr, FixtureEntry, FixtureOcr, Ocr};
+    use crate::settings::Settings;
+
+    use super::*;
+
+    fn words(texts: &[&str]) -> Vec<Word> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| Word::new(t.to_string(), i as f64 * 100.0, 0.0, 30.0, 80.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_row() {
+        let row = parse_row(Position::First, &words(&["naari", "+15", "42"])).unwrap();
+        assert_eq!(row.name, "naari");
+        assert_eq!(row.points, Some(15));
+        assert_eq!(row.total, Some(42));
+
+        // 全角や、+と数字が分かれていても読む
+        let row = parse_row(
+            Position::Fifth,
+            &words(&["1", "な", "ー", "り", "+", "８", "１０３"]),
+        )
+        .unwrap();
+        assert_eq!(row.position, Position::Fifth);
+        assert_eq!(row.name, "なーり");
+        assert_eq!(row.points, Some(8));
+        assert_eq!(row.total, Some(103));
+
+        // 点数が読めなくても名前は残す
+        let row = parse_row(Position::Twelfth, &words(&["Player"])).unwrap();
+        assert_eq!((row.points, row.total), (None, None));
+
+        // 名前が読めない行は捨てる
+        assert_eq!(parse_row(Position::Second, &words(&["+12", "30"])), None);
+    }
+
+    // 1回の読み取りで、上から順に認識する行ごとの単語
+    // 認識は1行ずつ順番に行われるので、何回目の認識かで結果を決める
+    fn fixture(reads: &[&[&[&str]]]) -> FixtureOcr {
+        let entries = reads
+            .iter()
+            .enumerate()
+            .flat_map(|(read, rows)| {
+                rows.iter()
+                    .enumerate()
+                    .map(move |(row, texts)| FixtureEntry {
+                        index: read * LINES + row,
+                        hash: String::new(),
+                        words: words(texts),
+                    })
+            })
+            .collect();
+        FixtureOcr::new(entries)
+    }
+
+    // 決めた回数目の認識だけ失敗する
+    struct FailingOcr {
+        inner: FixtureOcr,
+        fail_at: Vec<usize>,
+        count: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Ocr for FailingOcr {
+        async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+            let words = self.inner.recognize(image).await?;
+            if self
+                .fail_at
+                .contains(&self.count.fetch_add(1, Ordering::Relaxed))
+            {
+                anyhow::bail!("failed to recognize");
+            }
+            Ok(words)
+        }
+    }
+
+    // 表を読み終わるまで、フレームを流し続ける
+    async fn read_table(ocr: impl Ocr + 'static) -> MogiResult {
+        let pool = OcrPool::new(Box::new(ocr), 1, Duration::from_secs(5));
+        let mut mogi_result = MogiResult::new();
+        mogi_result.set_current_course(Course::new("ワリオスタジアム".to_string(), Console::DS));
+        mogi_result.set_current_position(Position::First);
+
+        let mut reader = ResultsTableReader::new();
+        reader.start(0);
+        for i in 0..100u8 {
+            // 同じ画像の認識は使い回されるので、フレームごとに中身を変える
+            let image = RgbImage::from_pixel(WIDTH as u32, HEIGHT as u32, Rgb([i, i, i]));
+            let frame = Frame::new(image, Duration::ZERO, i as u64, "test".into());
+            reader.observe(&frame, &pool, &mut mogi_result);
+            if reader.index.is_none() {
+                break;
+            }
+            for ticket in reader.pending.clone().unwrap_or_default() {
+                let _ = ticket.result().await;
+            }
+        }
+        assert!(reader.index.is_none());
+        mogi_result
+    }
+
+    fn totals(mogi_result: &MogiResult) -> Vec<Option<u32>> {
+        let race = mogi_result.iter_races().next().unwrap();
+        race.table().iter().map(|r| r.total).collect()
+    }
+
+    #[tokio::test]
+    async fn test_reads_after_points_settle() {
+        // 順位が決まった直後は、合計点が加算されている途中
+        let ocr = fixture(&[
+            &[&["naari", "+15", "42"], &["Player", "+12", "30"]],
+            &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+            &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+        ]);
+        let mogi_result = read_table(ocr).await;
+        assert_eq!(totals(&mogi_result), vec![Some(57), Some(42)]);
+    }
+
+    #[tokio::test]
+    async fn test_ignores_incomplete_reads() {
+        // 同じ行の認識が2回続けて失敗しても、その行が欠けた表を残さない
+        let ocr = FailingOcr {
+            inner: fixture(&[
+                &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+                &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+                &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+                &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+            ]),
+            fail_at: vec![1, LINES + 1],
+            count: AtomicUsize::new(0),
+        };
+        let mogi_result = read_table(ocr).await;
+        assert_eq!(totals(&mogi_result), vec![Some(57), Some(42)]);
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_total_scores() {
+        // 落ち着く前に総合順位の画面に変わったら、それまでに読んだ表を残す
+        let ocr = fixture(&[
+            &[&["naari", "+15", "57"], &["Player", "+12", "42"]],
+            &[&["naari", "57"], &["Player", "42"]],
+        ]);
+        let mogi_result = read_table(ocr).await;
+        assert_eq!(totals(&mogi_result), vec![Some(57), Some(42)]);
+    }
+
+    // 実際のリザルト画面(1280x720)を test_assets/results_screen.png に置き、実際のOCRで読む
+    // 行の範囲がずれていると、名前や点数が読めない行が出る
+    #[tokio::test]
+    #[ignore = "needs a real results screen in test_assets and an OCR backend"]
+    async fn test_real_results_screen() -> anyhow::Result<()> {
+        let image = image::open("./test_assets/results_screen.png")?.to_rgb8();
+        assert_eq!(image.dimensions(), (WIDTH as u32, HEIGHT as u32));
+        let frame = Frame::new(image, Duration::ZERO, 0, "test".into());
+        let settings = Settings::new("".to_string(), false, "INFO".to_string(), false);
+        let pool = OcrPool::new(create_ocr(&settings), 1, Duration::from_secs(30));
+        for i in 0..LINES {
+            let words = pool.submit(&frame, row_region(i)).result().await?;
+            let row = parse_row(Position::from_index(i).unwrap(), &words);
+            let row = row.unwrap_or_else(|| panic!("row {i} is not read: {words:?}"));
+            assert!(row.points.is_some() && row.total.is_some(), "{row:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_region() {
+        // すべての行が画面に収まり、上から順に並ぶ
+        for i in 0..LINES {
+            let region = row_region(i);
+            assert!(region.y + region.height <= HEIGHT as u32);
+            assert!(region.x + region.width <= WIDTH as u32);
+            if i > 0 {
+                assert!(region.y > row_region(i - 1).y);
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::mogi_result::MogiResult;
+
+/// 模擬の進行状況
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MogiState {
+    // ロード画面でコースを探す
+    Course,
+    // レース中。リザルト画面を待つ
+    RaceFinish,
+    // リザルト画面で順位を探す
+    Position,
+    // 総合順位が出るのを待って保存する
+    CaptureTotalScores,
+}
+
+impl MogiState {
+    /// この状態で必要なfps。Noneの場合は設定のfpsで動かす
+    pub fn frame_rate(self) -> Option<f64> {
+        match self {
+            // ロード画面の黒帯は数秒出ているので、低いfpsで十分
+            MogiState::Course => Some(5.0),
+            // レース中は何も検出しないので、リザルト画面が出たのに気付ければ十分
+            MogiState::RaceFinish => Some(10.0),
+            MogiState::Position | MogiState::CaptureTotalScores => None,
+        }
+    }
+}
+
+impl Display for MogiState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MogiState::Course => write!(f, "Course"),
+            MogiState::RaceFinish => write!(f, "RaceFinish"),
+            MogiState::Position => write!(f, "Position"),
+            MogiState::CaptureTotalScores => write!(f, "CaptureTotalScores"),
+        }
+    }
+}
+
+/// 状態が変わった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    // Detectorが次の画面を見つけた
+    Detected,
+    // 通信エラーのダイアログが出た
+    Error,
+    // 状態ごとの制限時間を過ぎた
+    Timeout,
+    // GUIで手動で直された
+    Manual,
+}
+
+// 見つかったものから起こしてよい状態の変化
+const TRANSITIONS: [(MogiState, MogiState, Trigger); 8] = [
+    (MogiState::Course, MogiState::RaceFinish, Trigger::Detected),
+    // ロード画面を見逃して、リザルト画面が出た
+    (MogiState::Course, MogiState::Position, Trigger::Detected),
+    // リザルト画面を見逃して、次のロード画面が出た
+    (
+        MogiState::RaceFinish,
+        MogiState::RaceFinish,
+        Trigger::Detected,
+    ),
+    (
+        MogiState::RaceFinish,
+        MogiState::Position,
+        Trigger::Detected,
+    ),
+    (MogiState::RaceFinish, MogiState::Course, Trigger::Error),
+    (
+        MogiState::Position,
+        MogiState::CaptureTotalScores,
+        Trigger::Detected,
+    ),
+    (MogiState::Position, MogiState::Course, Trigger::Error),
+    (
+        MogiState::CaptureTotalScores,
+        MogiState::Course,
+        Trigger::Detected,
+    ),
+];
+
+struct Timeout {
+    state: MogiState,
+    after: Duration,
+    fallback: MogiState,
+    // 制限時間を過ぎたときに結果に対してすること
+    action: Option<fn(&mut MogiResult)>,
+}
+
+// 画面を見逃しても同じ状態に留まり続けないように、制限時間を過ぎたら次に進む
+// 時間はフレームのタイムスタンプで測る
+const TIMEOUTS: [Timeout; 4] = [
+    // ロード画面を見逃しても、レースの結果は記録できるようにする
+    Timeout {
+        state: MogiState::Course,
+        after: Duration::from_secs(120),
+        fallback: MogiState::RaceFinish,
+        action: Some(MogiResult::mark_current_course_missed),
+    },
+    // リザルト画面を見逃した場合は、次のレースのコースを探す
+    Timeout {
+        state: MogiState::RaceFinish,
+        after: Duration::from_secs(360),
+        fallback: MogiState::Course,
+        action: None,
+    },
+    Timeout {
+        state: MogiState::Position,
+        after: Duration::from_secs(30),
+        fallback: MogiState::Course,
+        action: None,
+    },
+    // 総合順位の画面を見逃した場合は、保存せずに次のレースのコースを探す
+    Timeout {
+        state: MogiState::CaptureTotalScores,
+        after: Duration::from_secs(30),
+        fallback: MogiState::Course,
+        action: None,
+    },
+];
+
+// 覚えておく状態の変化の数
+const HISTORY_SIZE: usize = 64;
+
+/// 状態の変化の記録
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionRecord {
+    // 状態が変わったフレームのタイムスタンプ
+    pub at: Duration,
+    pub from: MogiState,
+    pub to: MogiState,
+    pub trigger: Trigger,
+}
+
+/// 今の状態と、いつその状態になったかを覚えておく
+/// 状態の変化は遷移表と照らし合わせて、ログに残す
+pub struct StateMachine {
+    state: MogiState,
+    // 今の状態になったフレームのタイムスタンプ。最初のフレームを受け取るまではNone
+    entered_at: Option<Duration>,
+    history: VecDeque<TransitionRecord>,
+}
+
+impl StateMachine {
+    pub fn new(state: MogiState) -> StateMachine {
+        StateMachine {
+            state,
+            entered_at: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> MogiState {
+        self.state
+    }
+
+    #[allow(dead_code)]
+    pub fn history(&self) -> impl Iterator<Item = &TransitionRecord> {
+        self.history.iter()
+    }
+
+    /// 今の状態になってからの時間
+    pub fn elapsed(&mut self, now: Duration) -> Duration {
+        let entered_at = *self.entered_at.get_or_insert(now);
+        now.saturating_sub(entered_at)
+    }
+
+    /// 制限時間を過ぎていたら、代わりの状態に移ってtrueを返す
+    pub fn check_timeout(&mut self, now: Duration, mogi_result: &mut MogiResult) -> bool {
+        let Some(timeout) = TIMEOUTS.iter().find(|t| t.state == self.state) else {
+            return false;
+        };
+        if self.elapsed(now) < timeout.after {
+            return false;
+        }
+        log::warn!(
+            "{} timed out after {:?}, fall back to {}",
+            self.state,
+            timeout.after,
+            timeout.fallback
+        );
+        if let Some(action) = timeout.action {
+            action(mogi_result);
+        }
+        self.record(timeout.fallback, Trigger::Timeout, now);
+        true
+    }
+
+    /// 見つかったものから状態を変える。遷移表にない変化はログに残す
+    pub fn transition(&mut self, to: MogiState, trigger: Trigger, now: Duration) {
+        let declared = TRANSITIONS
+            .iter()
+            .any(|(f, t, tr)| *f == self.state && *t == to && *tr == trigger);
+        if !declared {
+            // 遷移表の更新漏れ。止めずに記録だけ残す
+            log::error!(
+                "undeclared transition: {} -> {} ({:?})",
+                self.state,
+                to,
+                trigger
+            );
+        }
+        self.record(to, trigger, now);
+    }
+
+    /// GUIからの修正などで、見つかったものによらずに状態を変える
+    pub fn force(&mut self, state: MogiState, now: Duration) {
+        self.record(state, Trigger::Manual, now);
+    }
+
+    fn record(&mut self, to: MogiState, trigger: Trigger, now: Duration) {
+        let record = TransitionRecord {
+            at: now,
+            from: self.state,
+            to,
+            trigger,
+        };
+        log::info!(
+            "transition: {} -> {} ({:?}) at {:?}",
+            record.from,
+            record.to,
+            record.trigger,
+            record.at
+        );
+        self.history.push_back(record);
+        if self.history.len() > HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.state = to;
+        self.entered_at = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::race_result::Position;
+
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut mogi_result = MogiResult::new();
+        let mut machine = StateMachine::new(MogiState::Course);
+        assert!(!machine.check_timeout(secs(0), &mut mogi_result));
+        machine.transition(MogiState::RaceFinish, Trigger::Detected, secs(1));
+        machine.transition(MogiState::Position, Trigger::Detected, secs(2));
+        assert_eq!(machine.elapsed(secs(5)), secs(3));
+
+        // 順位が出ないまま制限時間を過ぎたら、次のコースを探す
+        assert!(!machine.check_timeout(secs(31), &mut mogi_result));
+        assert!(machine.check_timeout(secs(32), &mut mogi_result));
+        assert_eq!(machine.state(), MogiState::Course);
+
+        // ロード画面を見逃したら、コースがわからないままレースを待つ
+        assert!(!machine.check_timeout(secs(151), &mut mogi_result));
+        assert!(machine.check_timeout(secs(152), &mut mogi_result));
+        assert_eq!(machine.state(), MogiState::RaceFinish);
+        mogi_result.set_current_position(Position::First);
+        assert_eq!(mogi_result.iter_races().next().unwrap().course(), None);
+
+        machine.force(MogiState::RaceFinish, secs(3601));
+        let triggers = machine
+            .history()
+            .map(|r| (r.to, r.trigger))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            triggers,
+            vec![
+                (MogiState::RaceFinish, Trigger::Detected),
+                (MogiState::Position, Trigger::Detected),
+                (MogiState::Course, Trigger::Timeout),
+                (MogiState::RaceFinish, Trigger::Timeout),
+                (MogiState::RaceFinish, Trigger::Manual),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_every_state_can_go_back_to_course() {
+        // どの状態からでも、いつかはコースを探す状態に戻れる
+        for state in [
+            MogiState::RaceFinish,
+            MogiState::Position,
+            MogiState::CaptureTotalScores,
+        ] {
+            let leaves = TRANSITIONS
+                .iter()
+                .any(|(from, to, _)| *from == state && *to == MogiState::Course)
+                || TIMEOUTS.iter().any(|t| t.state == state);
+            assert!(leaves, "{state} never goes back to Course");
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+
+use crate::frame::Frame;
+use crate::ocr::{OcrPool, OcrTicket};
+use crate::race_result::Position;
+
+use super::position_detector::yellow_line_index;
+use super::results_table_reader::{parse_row, row_region};
+use super::{Detector, Observation, ObservationKind};
+
+/// 総合順位の画面を探す
+/// リザルト画面の表と同じ見た目だが、獲得点数(+15など)が消えて合計点だけになる
+/// 自分の行をOCRして、名前と合計点があって獲得点数がなければ総合順位の画面とする
+#[derive(Default)]
+pub struct TotalScoresDetector {
+    // 認識中の行の順位と、その認識
+    pending: Option<(Position, OcrTicket)>,
+}
+
+impl TotalScoresDetector {
+    pub fn new() -> TotalScoresDetector {
+        log::info!("TotalScoresDetector");
+        TotalScoresDetector::default()
+    }
+}
+
+#[async_trait]
+impl Detector for TotalScoresDetector {
+    async fn observe(
+        &mut self,
+        frame: &Frame,
+        ocr: &OcrPool,
+    ) -> anyhow::Result<Option<Observation>> {
+        // 表が出ていなければOCRしない
+        let Some(index) = yellow_line_index(frame.image()) else {
+            self.pending = None;
+            return Ok(None);
+        };
+        let mut observation = None;
+        if let Some(result) = self.pending.as_ref().and_then(|(_, t)| t.try_result()) {
+            let (position, _) = self.pending.take().unwrap();
+            match result {
+                Ok(words) => {
+                    let row = parse_row(position, &words);
+                    log::trace!("total scores row: {:?}", row);
+                    if row.is_some_and(|r| r.points.is_none() && r.total.is_some()) {
+                        observation = Some(Observation::new(ObservationKind::TotalScores, 1.0));
+                    }
+                }
+                Err(e) => log::error!("Error: {:?}", e),
+            }
+        }
+        if self.pending.is_none() {
+            if let Some(position) = Position::from_index(index) {
+                self.pending = Some((position, ocr.submit(frame, row_region(index))));
+            }
+        }
+        Ok(observation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::ocr::{FixtureEntry, FixtureOcr};
+    use crate::size::{HEIGHT, WIDTH};
+    use crate::word::Word;
+
+    use super::*;
+
+    async fn observe_with(texts: &[&str], image: RgbImage) -> anyhow::Result<Option<Observation>> {
+        let words = texts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| Word::new(t.to_string(), i as f64 * 100.0, 0.0, 30.0, 80.0))
+            .collect();
+        let ocr = FixtureOcr::new(vec![FixtureEntry {
+            index: 0,
+            hash: String::new(),
+            words,
+        }]);
+        let pool = OcrPool::new(Box::new(ocr), 1, Duration::from_secs(5));
+        let frame = Frame::new(image, Duration::ZERO, 0, "test".into());
+        let mut detector = TotalScoresDetector::new();
+        // 最初のフレームでは認識を要求するだけで、結果は待たない
+        assert_eq!(detector.observe(&frame, &pool).await?, None);
+        if let Some((_, ticket)) = detector.pending.as_ref() {
+            ticket.clone().result().await?;
+        }
+        detector.observe(&frame, &pool).await
+    }
+
+    // 1行目が自分の行として黄色くなっている表
+    fn table() -> RgbImage {
+        RgbImage::from_pixel(WIDTH as u32, HEIGHT as u32, Rgb([255, 255, 0]))
+    }
+
+    #[tokio::test]
+    async fn test_total_scores_detector() -> anyhow::Result<()> {
+        let observation = observe_with(&["naari", "57"], table()).await?;
+        assert_eq!(
+            observation.map(|o| o.kind),
+            Some(ObservationKind::TotalScores)
+        );
+
+        // 獲得点数が出ている間はレースのリザルト画面
+        assert_eq!(observe_with(&["naari", "+15", "57"], table()).await?, None);
+        // 表が出ていなければ何も見つけない
+        let blank = RgbImage::new(WIDTH as u32, HEIGHT as u32);
+        assert_eq!(observe_with(&["naari", "57"], blank).await?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::RgbImage;
+
+/// キャプチャしたフレーム
+/// `timestamp` はキャプチャを開始してからの経過時間(ストリーム時間)で、
+/// 動画ファイルなどの場合は実時間ではなく動画上の再生位置になる
+#[derive(Debug, Clone)]
+pub struct Frame {
+    image: Arc<RgbImage>,
+    timestamp: Duration,
+    sequence: u64,
+    source_id: Arc<str>,
+}
+
+impl Frame {
+    pub fn new(image: RgbImage, timestamp: Duration, sequence: u64, source_id: Arc<str>) -> Self {
+        Self {
+            image: Arc::new(image),
+            timestamp,
+            sequence,
+            source_id,
+        }
+    }
+
+    pub fn image(&self) -> &RgbImage {
+        &self.image
+    }
+
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn source_id(&self) -> &str {
+        &self.source_id
+    }
+
+    /// `earlier` からの経過時間。ソースが切り替わって時間が戻った場合は0
+    pub fn elapsed_since(&self, earlier: Duration) -> Duration {
+        self.timestamp.saturating_sub(earlier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elapsed_since() {
+        let frame = Frame::new(
+            RgbImage::new(1, 1),
+            Duration::from_secs(10),
+            0,
+            "test".into(),
+        );
+        assert_eq!(
+            frame.elapsed_since(Duration::from_secs(6)),
+            Duration::from_secs(4)
+        );
+        assert_eq!(frame.elapsed_since(Duration::from_secs(12)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::frame::Frame;
+
+/// ProducerからConsumerへのフレームの受け渡しの統計
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    produced: AtomicU64,
+    processed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl FrameStats {
+    pub fn produced(&self) -> u64 {
+        self.produced.load(Ordering::Relaxed)
+    }
+
+    pub fn processed(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    // Consumerが処理する前に新しいフレームで上書きされた数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    slot: Mutex<Option<Frame>>,
+    // 新しいフレームが入った、またはSenderが閉じた
+    filled: Notify,
+    // フレームが取り出された、またはReceiverが閉じた
+    emptied: Notify,
+    sender_closed: AtomicBool,
+    receiver_closed: AtomicBool,
+    stats: Arc<FrameStats>,
+}
+
+/// フレームを1枚だけ保持するスロットを作る
+/// Consumerが遅れている場合は古いフレームを捨てて、常に最新のフレームを渡す
+pub fn frame_slot() -> (FrameSender, FrameReceiver) {
+    let shared = Arc::new(Shared::default());
+    (
+        FrameSender {
+            shared: shared.clone(),
+        },
+        FrameReceiver { shared },
+    )
+}
+
+#[derive(Debug)]
+pub struct FrameSender {
+    shared: Arc<Shared>,
+}
+
+impl FrameSender {
+    pub fn stats(&self) -> Arc<FrameStats> {
+        self.shared.stats.clone()
+    }
+
+    /// スロットにフレームを入れる。処理されていないフレームがあれば捨てる
+    pub fn send(&self, frame: Frame) -> anyhow::Result<()> {
+        if self.shared.receiver_closed.load(Ordering::Acquire) {
+            return Err(anyhow::anyhow!("frame receiver is closed"));
+        }
+        let previous = self.shared.slot.lock().unwrap().replace(frame);
+        self.shared.stats.produced.fetch_add(1, Ordering::Relaxed);
+        if let Some(previous) = previous {
+            self.shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            log::trace!("dropped frame #{}", previous.sequence());
+        }
+        self.shared.filled.notify_one();
+        Ok(())
+    }
+
+    /// スロットが空くのを待ってからフレームを入れる
+    /// 動画ファイルなど、フレームを捨てたくないソースで使う
+    pub async fn send_lossless(&self, frame: Frame) -> anyhow::Result<()> {
+        let mut frame = Some(frame);
+        loop {
+            if self.shared.receiver_closed.load(Ordering::Acquire) {
+                return Err(anyhow::anyhow!("frame receiver is closed"));
+            }
+            {
+                let mut slot = self.shared.slot.lock().unwrap();
+                if slot.is_none() {
+                    *slot = frame.take();
+                    break;
+                }
+            }
+            self.shared.emptied.notified().await;
+        }
+        self.shared.stats.produced.fetch_add(1, Ordering::Relaxed);
+        self.shared.filled.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        self.shared.sender_closed.store(true, Ordering::Release);
+        self.shared.filled.notify_one();
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+
+impl FrameReceiver {
+    pub fn stats(&self) -> Arc<FrameStats> {
+        self.shared.stats.clone()
+    }
+
+    /// 最新のフレームを受け取る。Senderが閉じていて残りのフレームもなければNone
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.shared.slot.lock().unwrap().take() {
+                self.shared.stats.processed.fetch_add(1, Ordering::Relaxed);
+                self.shared.emptied.notify_one();
+                return Some(frame);
+            }
+            if self.shared.sender_closed.load(Ordering::Acquire) {
+                return None;
+            }
+            self.shared.filled.notified().await;
+        }
+    }
+}
+
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+        self.shared.emptied.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::RgbImage;
+
+    use super::*;
+
+    fn frame(sequence: u64) -> Frame {
+        Frame::new(
+            RgbImage::new(1, 1),
+            Duration::from_millis(sequence * 33),
+            sequence,
+            "test".into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_latest_frame_wins() -> anyhow::Result<()> {
+        let (tx, mut rx) = frame_slot();
+        for i in 0..5 {
+            tx.send(frame(i))?;
+        }
+        assert_eq!(rx.recv().await.map(|f| f.sequence()), Some(4));
+        let stats = rx.stats();
+        assert_eq!(stats.produced(), 5);
+        assert_eq!(stats.dropped(), 4);
+        assert_eq!(stats.processed(), 1);
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_lossless() -> anyhow::Result<()> {
+        let (tx, mut rx) = frame_slot();
+        let producer = tokio::spawn(async move {
+            for i in 0..10 {
+                tx.send_lossless(frame(i)).await.unwrap();
+            }
+        });
+        let mut received = Vec::new();
+        while let Some(frame) = rx.recv().await {
+            received.push(frame.sequence());
+        }
+        producer.await?;
+        assert_eq!(received, (0..10).collect::<Vec<u64>>());
+        assert_eq!(rx.stats().dropped(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_after_receiver_closed() {
+        let (tx, rx) = frame_slot();
+        drop(rx);
+        assert!(tx.send(frame(0)).is_err());
+        assert!(tx.send_lossless(frame(1)).await.is_err());
+    }
+}
@@ -3,27 +3,22 @@ use eframe::{
         self, CentralPanel, ComboBox, FontData, FontDefinitions, Grid, Key, Layout, ScrollArea,
     },
     emath::Align,
-    epaint::{ColorImage, FontFamily},
+    epaint::{Color32, ColorImage, FontFamily},
     CreationContext, Frame,
 };
 use egui_extras::{Column, RetainedImage, TableBuilder};
 use image::imageops::FilterType;
-use std::{
-    sync::{Arc, Mutex},
-    time::{Duration, Instant},
-};
+use image::RgbImage;
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
-    capture::{open_directshow_device, open_msmf_device},
-    capture_raw::{
-        capture_with_escapi, capture_with_opencv, get_directshow_device_name_map,
-        get_msmf_device_name_map,
-    },
+    capture::{device_enumerator, CaptureStatus},
     courses::{Course, COURSES, STRING_COURSE_MAP},
+    frame_slot::FrameStats,
     mogi_result::MogiResult,
     race_result::Position,
-    settings::Settings,
+    settings::{CaptureSource, OcrBackend, Settings},
 };
 
 use super::course_dropdown::DropDownBox;
@@ -35,6 +30,8 @@ const LOG_LEVELS: [&str; 6] = ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"]
 #[derive(Debug, Clone)]
 pub enum Event {
     EditMogiResult(MogiResult),
+    // 直近のフレームをrecordings/に保存する
+    DumpRecording,
 }
 
 #[derive(Debug, Clone)]
@@ -68,6 +65,14 @@ struct BufSettings {
     directshow: bool,
     log_level: String,
     write_log_to_file: bool,
+    source: CaptureSource,
+    source_path: String,
+    playback_speed: f64,
+    frame_rate: f64,
+    recorder_seconds: u32,
+    ocr_backend: OcrBackend,
+    ocr_fixture_path: String,
+    record_ocr: bool,
 }
 
 // Settings と BufSettingts は相互に変換できるようにする
@@ -78,30 +83,56 @@ impl From<Settings> for BufSettings {
             directshow: settings.directshow(),
             log_level: settings.log_level().to_string(),
             write_log_to_file: settings.write_log_to_file(),
+            source: settings.source(),
+            source_path: settings.source_path().to_string(),
+            playback_speed: settings.playback_speed(),
+            frame_rate: settings.frame_rate(),
+            recorder_seconds: settings.recorder_seconds(),
+            ocr_backend: settings.ocr_backend(),
+            ocr_fixture_path: settings.ocr_fixture_path().to_string(),
+            record_ocr: settings.record_ocr(),
         }
     }
 }
 
 impl From<BufSettings> for Settings {
     fn from(buf_settings: BufSettings) -> Self {
-        Self::new(
+        let mut settings = Self::new(
             buf_settings.device_name,
             buf_settings.directshow,
             buf_settings.log_level,
             buf_settings.write_log_to_file,
-        )
+        );
+        settings.set_source(buf_settings.source);
+        settings.set_source_path(buf_settings.source_path);
+        settings.set_playback_speed(buf_settings.playback_speed);
+        settings.set_frame_rate(buf_settings.frame_rate);
+        settings.set_recorder_seconds(buf_settings.recorder_seconds);
+        settings.set_ocr_backend(buf_settings.ocr_backend);
+        settings.set_ocr_fixture_path(buf_settings.ocr_fixture_path);
+        settings.set_record_ocr(buf_settings.record_ocr);
+        settings
     }
 }
 
+/// Producerから受け取るキャプチャの状態
+pub struct CaptureFeed {
+    pub status_rx: Arc<Mutex<Receiver<CaptureStatus>>>,
+    pub frame_stats: Arc<FrameStats>,
+    // Producerが縮小して送ってくるプレビュー用のフレーム
+    pub preview_rx: Arc<Mutex<Receiver<RgbImage>>>,
+}
+
 pub struct App {
     // Sender/Receiver for async notifications.
     tx: Arc<Mutex<Sender<Event>>>,
     rx: Arc<Mutex<Receiver<MogiResult>>>,
 
     settings_tx: Arc<Mutex<Sender<Settings>>>,
+    capture_feed: CaptureFeed,
+    capture_status: CaptureStatus,
     on_settings: bool,
-    msmf_device_names: Vec<String>,
-    directshow_device_names: Vec<String>,
+    device_names: Vec<String>,
     buf_settings: BufSettings,
 
     courses: Vec<Course>,
@@ -110,7 +141,6 @@ pub struct App {
     opened_race: Option<OpenedRace>,
 
     capture_preview: Option<RetainedImage>,
-    last_preview_updated: Instant,
 }
 
 impl App {
@@ -119,6 +149,7 @@ impl App {
         tx: Arc<Mutex<Sender<Event>>>,
         rx: Arc<Mutex<Receiver<MogiResult>>>,
         settings_tx: Arc<Mutex<Sender<Settings>>>,
+        capture_feed: CaptureFeed,
         default_settings: Settings,
     ) -> Self {
         let mut fonts = FontDefinitions::default();
@@ -137,24 +168,16 @@ impl App {
             tx,
             rx,
             settings_tx,
+            capture_feed,
+            capture_status: CaptureStatus::Disconnected,
             on_settings: false,
-            msmf_device_names: get_msmf_device_name_map()
-                .unwrap()
-                .values()
-                .cloned()
-                .collect(),
-            directshow_device_names: get_directshow_device_name_map()
-                .unwrap()
-                .values()
-                .cloned()
-                .collect(),
+            device_names: device_enumerator(default_settings.directshow()).device_names(),
             buf_settings: default_settings.into(),
             courses: COURSES.try_lock().unwrap().clone(),
             mogi_result: MogiResult::new(),
             draft_mogi_result: None,
             opened_race: None,
             capture_preview: None,
-            last_preview_updated: Instant::now(),
         }
     }
 
@@ -163,53 +186,51 @@ impl App {
         self.settings_tx.lock().unwrap().try_send(settings).unwrap();
     }
 
+    // 設定画面を開いている間だけ受け取る。受け取らない間はProducerも縮小をしない
     fn refresh_capture_preview(&mut self, width: f32) {
-        if self.buf_settings.device_name.is_empty() {
-            return;
+        let mut img = None;
+        if let Ok(mut preview_rx) = self.capture_feed.preview_rx.lock() {
+            while let Ok(latest) = preview_rx.try_recv() {
+                img = Some(latest);
+            }
         }
-        if self.last_preview_updated.elapsed() > Duration::from_millis(1000 / 30) {
-            self.last_preview_updated = Instant::now();
-            let device_name = self.buf_settings.device_name.clone();
-            let img = if self.buf_settings.directshow {
-                let device = match open_directshow_device(&device_name) {
-                    Ok(device) => device,
-                    Err(err) => {
-                        log::error!("failed to open directshow device: {}", err);
-                        return;
-                    }
-                };
-                let mut device = device.lock().unwrap();
-                capture_with_opencv(&mut device).ok()
-            } else {
-                let device = match open_msmf_device(&device_name) {
-                    Ok(device) => device,
-                    Err(err) => {
-                        log::error!("failed to open msmf device: {}", err);
-                        return;
-                    }
-                };
-                let device = device.lock().unwrap();
-                capture_with_escapi(&device).ok()
-            };
 
-            if let Some(img) = img {
-                let img = image::imageops::resize(
-                    &img,
-                    width as _,
-                    (width / 16.0 * 9.0) as _,
-                    FilterType::Nearest,
-                );
-                let width = img.width();
-                let height = img.height();
-                self.capture_preview = Some(RetainedImage::from_color_image(
-                    "aaa",
-                    ColorImage::from_rgb([width as _, height as _], img.as_raw()),
-                ))
-            };
-        }
+        if let Some(img) = img {
+            let img = image::imageops::resize(
+                &img,
+                width as _,
+                (width / 16.0 * 9.0) as _,
+                FilterType::Nearest,
+            );
+            let width = img.width();
+            let height = img.height();
+            self.capture_preview = Some(RetainedImage::from_color_image(
+                "aaa",
+                ColorImage::from_rgb([width as _, height as _], img.as_raw()),
+            ))
+        };
     }
 }
 
+fn capture_status_indicator(ui: &mut egui::Ui, status: CaptureStatus, stats: &FrameStats) {
+    let color = match status {
+        CaptureStatus::Healthy => Color32::GREEN,
+        CaptureStatus::Frozen | CaptureStatus::NoSignal | CaptureStatus::Reconnecting => {
+            Color32::YELLOW
+        }
+        CaptureStatus::Disconnected => Color32::RED,
+    };
+    ui.colored_label(color, "●")
+        .on_hover_text(status.to_string());
+    // 検出が追いつかずに捨てたフレームの数
+    ui.weak(format!(
+        "処理 {} / 破棄 {}",
+        stats.processed(),
+        stats.dropped()
+    ))
+    .on_hover_text(format!("取得したフレーム: {}", stats.produced()));
+}
+
 fn course_dropdown(ui: &mut egui::Ui, courses: &[Course], buffer: &mut String) {
     ui.group(|ui| {
         ui.add(DropDownBox::from_iter(
@@ -319,7 +340,9 @@ fn edit_view(
             if let Some(course) = binding.get(buffer) {
                 match index {
                     OpenedIndex::Result(idx) => draft_mogi_result.set_course(*idx, course.clone()),
-                    OpenedIndex::Current => draft_mogi_result.set_current_course(course.clone()),
+                    OpenedIndex::Current => {
+                        draft_mogi_result.correct_current_course(course.clone())
+                    }
                 }
             }
         }
@@ -393,6 +416,17 @@ fn show_view(mogi_result: &MogiResult, ui: &mut egui::Ui, tx: &Arc<Mutex<Sender<
                             });
                             row.col(|ui| {
                                 ui.label(race.course_name());
+                                // 推測したコースは、候補からワンクリックで選び直せる
+                                for candidate in race.candidates() {
+                                    if ui.small_button(candidate.to_string()).clicked() {
+                                        let mut new_mogi_result = mogi_result.clone();
+                                        new_mogi_result.set_course(i, candidate.clone());
+                                        tx.lock()
+                                            .unwrap()
+                                            .try_send(Event::EditMogiResult(new_mogi_result))
+                                            .unwrap();
+                                    }
+                                }
                             });
                             row.col(|ui| {
                                 ui.label(race.position().to_string());
@@ -420,13 +454,32 @@ fn show_view(mogi_result: &MogiResult, ui: &mut egui::Ui, tx: &Arc<Mutex<Sender<
                 .try_send(Event::EditMogiResult(MogiResult::new()))
                 .unwrap();
         }
+        if ui
+            .button("映像を保存")
+            .on_hover_text("直近の映像をrecordings/に保存する (設定で保持する秒数を指定する)")
+            .clicked()
+        {
+            tx.lock().unwrap().try_send(Event::DumpRecording).unwrap();
+        }
     });
     ui.separator();
     let current_course_name = mogi_result
         .current_course()
         .as_ref()
         .map_or("(Empty)".to_string(), |course| course.to_string());
-    ui.label(format!("現在のコース: {current_course_name}",));
+    ui.horizontal(|ui| {
+        ui.label(format!("現在のコース: {current_course_name}",));
+        for candidate in mogi_result.current_candidates() {
+            if ui.small_button(candidate.to_string()).clicked() {
+                let mut new_mogi_result = mogi_result.clone();
+                new_mogi_result.correct_current_course(candidate.clone());
+                tx.lock()
+                    .unwrap()
+                    .try_send(Event::EditMogiResult(new_mogi_result))
+                    .unwrap();
+            }
+        }
+    });
 
     let total_score = mogi_result.total_score();
     ui.label(format!("合計得点: {total_score}"));
@@ -436,30 +489,76 @@ fn settings_view(this: &mut App, ui: &mut egui::Ui, frame: &Frame) {
     ui.vertical(|ui| {
         ui.strong("設定");
         ui.separator();
-        if ui
-            .checkbox(
-                &mut this.buf_settings.directshow,
-                "DirectShowのデバイスを選択する",
-            )
-            .clicked()
-        {
-            this.buf_settings.device_name = "".to_string();
-        };
-        ui.label("キャプチャするデバイスを選択");
-        ComboBox::from_id_source(0)
-            .width(200.0)
-            .selected_text(this.buf_settings.device_name.clone())
-            .show_ui(ui, |ui| {
-                let device_names = if this.buf_settings.directshow {
-                    &this.directshow_device_names
-                } else {
-                    &this.msmf_device_names
-                };
-
-                device_names.iter().for_each(|dn| {
-                    ui.selectable_value(&mut this.buf_settings.device_name, dn.clone(), dn.clone());
-                })
-            });
+        ui.label("入力ソースを選択");
+        ui.horizontal(|ui| {
+            ui.radio_value(
+                &mut this.buf_settings.source,
+                CaptureSource::Device,
+                "キャプチャデバイス",
+            );
+            ui.radio_value(
+                &mut this.buf_settings.source,
+                CaptureSource::VideoFile,
+                "動画ファイル",
+            );
+            ui.radio_value(
+                &mut this.buf_settings.source,
+                CaptureSource::ImageSequence,
+                "連番画像",
+            );
+            ui.radio_value(
+                &mut this.buf_settings.source,
+                CaptureSource::Network,
+                "ネットワーク",
+            );
+        });
+        if this.buf_settings.source == CaptureSource::VideoFile {
+            ui.label("動画ファイルのパス");
+            ui.text_edit_singleline(&mut this.buf_settings.source_path);
+            ui.add(
+                egui::Slider::new(&mut this.buf_settings.playback_speed, 0.0..=8.0)
+                    .text("再生速度 (0で最速)"),
+            );
+        } else if this.buf_settings.source == CaptureSource::ImageSequence {
+            ui.label("画像のあるディレクトリ、またはglob (例: results/*/race_*.png)");
+            ui.text_edit_singleline(&mut this.buf_settings.source_path);
+        } else if this.buf_settings.source == CaptureSource::Network {
+            ui.label("ストリームのURL");
+            ui.label("MJPEG: http://192.168.0.10:8080/stream");
+            ui.label("RGB24: tcp://192.168.0.10:9000");
+            ui.text_edit_singleline(&mut this.buf_settings.source_path);
+        } else {
+            #[cfg(windows)]
+            if ui
+                .checkbox(
+                    &mut this.buf_settings.directshow,
+                    "DirectShowのデバイスを選択する",
+                )
+                .clicked()
+            {
+                this.buf_settings.device_name = "".to_string();
+                this.device_names = device_enumerator(this.buf_settings.directshow).device_names();
+            };
+            ui.label("キャプチャするデバイスを選択");
+            ComboBox::from_id_source(0)
+                .width(200.0)
+                .selected_text(this.buf_settings.device_name.clone())
+                .show_ui(ui, |ui| {
+                    this.device_names.iter().for_each(|dn| {
+                        ui.selectable_value(
+                            &mut this.buf_settings.device_name,
+                            dn.clone(),
+                            dn.clone(),
+                        );
+                    })
+                });
+        }
+        ui.add(
+            egui::Slider::new(&mut this.buf_settings.frame_rate, 1.0..=60.0)
+                .text("キャプチャする最大のfps"),
+        );
+        // 検出に使われているフレームをそのまま表示する
+        ui.label("現在の入力 (OKを押すと切り替わる)");
         let width = ui
             .ctx()
             .input(|i| i.viewport().inner_rect.unwrap().width() - 15.0);
@@ -485,6 +584,34 @@ fn settings_view(this: &mut App, ui: &mut egui::Ui, frame: &Frame) {
             &mut this.buf_settings.write_log_to_file,
             "ファイルにTRACEレベルのログを出力",
         );
+        ui.add(
+            egui::Slider::new(&mut this.buf_settings.recorder_seconds, 0..=10)
+                .text("バグ報告用に直近の映像を保持する秒数 (0で無効)"),
+        );
+        ui.label("文字認識に使うOCR");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut this.buf_settings.ocr_backend, OcrBackend::Auto, "自動");
+            ui.radio_value(
+                &mut this.buf_settings.ocr_backend,
+                OcrBackend::WinRt,
+                "Windows.Media.Ocr",
+            );
+            ui.radio_value(
+                &mut this.buf_settings.ocr_backend,
+                OcrBackend::Tesseract,
+                "Tesseract",
+            );
+            ui.radio_value(
+                &mut this.buf_settings.ocr_backend,
+                OcrBackend::Fixture,
+                "記録を再生",
+            );
+        });
+        ui.checkbox(&mut this.buf_settings.record_ocr, "OCRの結果を記録する");
+        if this.buf_settings.record_ocr || this.buf_settings.ocr_backend == OcrBackend::Fixture {
+            ui.label("OCRの結果を記録・再生するファイル");
+            ui.text_edit_singleline(&mut this.buf_settings.ocr_fixture_path);
+        }
     });
 }
 
@@ -506,9 +633,15 @@ impl eframe::App for App {
                 self.opened_race = None;
             }
         }
+        if let Ok(mut status_rx) = self.capture_feed.status_rx.lock() {
+            while let Ok(status) = status_rx.try_recv() {
+                self.capture_status = status;
+            }
+        }
         CentralPanel::default().show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.heading("lounge-memo");
+                capture_status_indicator(ui, self.capture_status, &self.capture_feed.frame_stats);
                 ui.with_layout(Layout::right_to_left(Align::Max), |ui| {
                     let settings_label = if self.on_settings { "OK" } else { "Settings" };
                     if ui.button(settings_label).clicked() {
@@ -2,11 +2,10 @@
 // modify to use with Course struct
 
 use eframe::egui::{Id, Key, Response, Ui, Widget};
-use kanaria::string::UCSStr;
-use kanaria::utils::ConvertTarget;
 use std::hash::Hash;
 
 use crate::courses::COURSE_SHORTHAND_MAP;
+use crate::normalize::normalize;
 
 /// Dropdown widget
 pub struct DropDownBox<
@@ -40,15 +39,6 @@ impl<'a, F: FnMut(&mut Ui, &str) -> Response, V: AsRef<str>, I: Iterator<Item =
     }
 }
 
-// 大文字英字を小文字に、ひらがなとカタカナをすべて半角ｶﾅに変換する
-fn to_half_width(s: &str) -> String {
-    UCSStr::from_str(s)
-        .katakana()
-        .narrow(ConvertTarget::ALL)
-        .lower_case()
-        .to_string()
-}
-
 impl<'a, F: FnMut(&mut Ui, &str) -> Response, V: AsRef<str>, I: Iterator<Item = V>> Widget
     for DropDownBox<'a, F, V, I>
 {
@@ -66,8 +56,8 @@ impl<'a, F: FnMut(&mut Ui, &str) -> Response, V: AsRef<str>, I: Iterator<Item =
         }
         let enter_pressed = r.ctx.input(|i| i.key_pressed(Key::Enter));
         if enter_pressed {
-            let lower = to_half_width(buf);
-            if let Some(course_name) = COURSE_SHORTHAND_MAP.lock().unwrap().get(&lower) {
+            // 略称はOCRの結果と同じように正規化して登録してある
+            if let Some(course_name) = COURSE_SHORTHAND_MAP.lock().unwrap().get(&normalize(buf)) {
                 *buf = course_name.to_owned();
                 ui.memory_mut(|m| m.close_popup());
             }
@@ -102,23 +92,29 @@ impl<'a, F: FnMut(&mut Ui, &str) -> Response, V: AsRef<str>, I: Iterator<Item =
 
 #[cfg(test)]
 mod test {
+    use once_cell::sync::Lazy;
+
+    use crate::courses::COURSES;
+
+    use super::*;
+
+    fn shorthand(s: &str) -> Option<String> {
+        // 略称はCOURSESの初期化時に登録される
+        Lazy::force(&COURSES);
+        COURSE_SHORTHAND_MAP
+            .lock()
+            .unwrap()
+            .get(&normalize(s))
+            .cloned()
+    }
+
     #[test]
-    fn test_to_half() {
-        assert_eq!(super::to_half_width("あいうえお"), "ｱｲｳｴｵ");
-        assert_eq!(super::to_half_width("アイウエオ"), "ｱｲｳｴｵ");
-        assert_eq!(super::to_half_width("ｱｲｳｴｵ"), "ｱｲｳｴｵ");
-        assert_eq!(super::to_half_width("abcde"), "abcde");
-        assert_eq!(super::to_half_width("ABCDE"), "abcde");
-        assert_eq!(super::to_half_width("ａｂｃｄｅ"), "abcde");
-        assert_eq!(super::to_half_width("ＡＢＣＤＥ"), "abcde");
-        assert_eq!(super::to_half_width("あいうえおabcde"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("あいうえおABCDE"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("あいうえおａｂｃｄｅ"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("あいうえおＡＢＣＤＥ"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("アイウエオabcde"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("アイウエオABCDE"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("アイウエオａｂｃｄｅ"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("アイウエオＡＢＣＤＥ"), "ｱｲｳｴｵabcde");
-        assert_eq!(super::to_half_width("ｱｲｳｴｵabcde"), "ｱｲｳｴｵabcde");
+    fn test_shorthand() {
+        // ひらがな、カタカナ、半角カナ、全角英字のどれで入力しても同じコースになる
+        for input in ["ﾏﾘｶｽ", "まりかす", "マリカス", "mks", "MKS", "ｍｋｓ"] {
+            assert_eq!(shorthand(input), Some("マリオカートスタジアム".to_string()));
+        }
+        assert_eq!(shorthand("どっすん"), Some("ドッスンいせき".to_string()));
+        assert_eq!(shorthand("存在しない略称"), None);
     }
 }
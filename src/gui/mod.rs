@@ -1,4 +1,4 @@
 mod app;
 mod course_dropdown;
 
-pub use app::{App, Event};
+pub use app::{App, CaptureFeed, Event};
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::courses::Course;
+use crate::normalize::normalize;
+
+const LEARNED_ALIASES_PATH: &str = "learned_aliases.json";
+
+pub static LEARNED_ALIASES: Lazy<Mutex<LearnedAliases>> = Lazy::new(|| {
+    let aliases = LearnedAliases::load(Path::new(LEARNED_ALIASES_PATH)).unwrap_or_else(|e| {
+        log::warn!("failed to load learned aliases: {:?}", e);
+        LearnedAliases::new(PathBuf::from(LEARNED_ALIASES_PATH))
+    });
+    Mutex::new(aliases)
+});
+
+/// ユーザーが手動で直したコースを、そのときのOCRの文字列と一緒に覚えておく
+/// 同じ読み間違いをしたときに、次からは直したコースになる
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LearnedAliases {
+    #[serde(skip)]
+    path: PathBuf,
+    // 正規化したOCRの文字列からコース
+    aliases: BTreeMap<String, Course>,
+}
+
+impl LearnedAliases {
+    pub fn new(path: PathBuf) -> LearnedAliases {
+        LearnedAliases {
+            path,
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<LearnedAliases> {
+        if !path.exists() {
+            return Ok(LearnedAliases::new(path.to_path_buf()));
+        }
+        let text = std::fs::read_to_string(path)?;
+        let mut aliases: LearnedAliases = serde_json::from_str(&text)?;
+        aliases.path = path.to_path_buf();
+        log::info!(
+            "loaded {} learned aliases from {}",
+            aliases.aliases.len(),
+            path.display()
+        );
+        Ok(aliases)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        std::fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, text: &str) -> Option<&Course> {
+        self.aliases.get(&normalize(text))
+    }
+
+    /// `texts` を読み取ったときは `course` だったことを覚えて、ファイルに保存する
+    pub fn learn(&mut self, texts: &[String], course: &Course) -> anyhow::Result<()> {
+        let mut changed = false;
+        for text in texts {
+            let key = normalize(text);
+            if key.trim().is_empty() || self.aliases.get(&key) == Some(course) {
+                continue;
+            }
+            log::info!("learned alias: {text} -> {course}");
+            self.aliases.insert(key, course.clone());
+            changed = true;
+        }
+        if changed {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::courses::Console;
+
+    use super::*;
+
+    #[test]
+    fn test_learn() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "lounge-memo-test-learned-aliases-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let course = Course::new("ドーナツへいや3".to_string(), Console::SFC);
+
+        let mut aliases = LearnedAliases::load(&path)?;
+        assert_eq!(aliases.get("SFC ド一ナツへいや8"), None);
+        aliases.learn(&["SFC ド一ナツへいや8".to_string()], &course)?;
+        // 正規化してから比べるので、全角やひらがなの違いは気にしない
+        assert_eq!(aliases.get("ＳＦＣ ドーナツヘイヤ8"), Some(&course));
+
+        // 保存したものを読み込み直しても使える
+        let aliases = LearnedAliases::load(&path)?;
+        assert_eq!(aliases.get("SFC ド一ナツへいや8"), Some(&course));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
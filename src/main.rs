@@ -2,9 +2,10 @@ use std::fs::read_to_string;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use consumer::Consumer;
-use gui::App;
+use gui::{App, CaptureFeed};
 use log::LevelFilter;
 use mogi_result::MogiResult;
 use settings::Settings;
@@ -12,17 +13,29 @@ use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
 use tokio::task;
 
+use crate::frame_slot::frame_slot;
+use crate::ocr::create_ocr;
 use crate::producer::Producer;
+use crate::recorder::FrameRecorder;
 
 mod capture;
 mod capture_raw;
+mod clock;
+mod console_badge;
 mod consumer;
+mod course_preview;
 mod courses;
 mod detector;
+mod frame;
+mod frame_slot;
 mod gui;
+mod learned_aliases;
 mod mogi_result;
+mod normalize;
+mod ocr;
 mod producer;
 mod race_result;
+mod recorder;
 mod settings;
 mod size;
 mod word;
@@ -89,20 +102,29 @@ async fn main() -> anyhow::Result<()> {
 
     let (from_gui_tx, from_gui_rx) = mpsc::channel(10);
     let (to_gui_tx, to_gui_rx) = mpsc::channel(10);
+    let (status_tx, status_rx) = mpsc::channel(10);
+    let (preview_tx, preview_rx) = mpsc::channel(1);
+    let (frame_rate_tx, frame_rate_rx) = mpsc::channel(10);
 
-    let (tx, rx) = mpsc::channel(10);
+    let ocr = create_ocr(&settings);
+    let recorder = FrameRecorder::new(Duration::from_secs(settings.recorder_seconds() as u64));
+    let (tx, rx) = frame_slot();
+    let frame_stats = tx.stats();
 
     std::thread::spawn(move || {
         rt.block_on(async {
             let producer = task::spawn(async move {
                 let mut producer = Producer;
-                producer.run(tx, settings_rx).await.unwrap();
+                producer
+                    .run(tx, settings_rx, status_tx, preview_tx, frame_rate_rx)
+                    .await
+                    .unwrap();
             });
 
             let consumer = task::spawn(async move {
-                let mut consumer = Consumer;
+                let mut consumer = Consumer::new(recorder, ocr);
                 consumer
-                    .run(&mut result, rx, to_gui_tx, from_gui_rx)
+                    .run(&mut result, rx, to_gui_tx, from_gui_rx, frame_rate_tx)
                     .await
                     .unwrap();
             });
@@ -145,6 +167,11 @@ async fn main() -> anyhow::Result<()> {
                 Arc::new(Mutex::new(from_gui_tx)),
                 Arc::new(Mutex::new(to_gui_rx)),
                 Arc::new(Mutex::new(settings_tx)),
+                CaptureFeed {
+                    status_rx: Arc::new(Mutex::new(status_rx)),
+                    frame_stats,
+                    preview_rx: Arc::new(Mutex::new(preview_rx)),
+                },
                 settings,
             ))
         }),
@@ -5,13 +5,22 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     courses::Course,
-    race_result::{Position, RaceResult},
+    race_result::{Position, RaceResult, ResultsRow},
 };
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MogiResult {
     races: Vec<RaceResult>,
     current_course: Option<Course>,
+    // 現在のコースを推測した場合の他の候補
+    #[serde(default)]
+    current_candidates: Vec<Course>,
+    // 現在のコースを探したときのOCRの文字列
+    #[serde(default)]
+    current_ocr_texts: Vec<String>,
+    // ロード画面を見逃して、コースがわからないままレースが始まった
+    #[serde(default)]
+    current_course_missed: bool,
     created_at: chrono::DateTime<chrono::Local>,
 }
 
@@ -20,25 +29,58 @@ impl MogiResult {
         MogiResult {
             races: Vec::new(),
             current_course: None,
+            current_candidates: Vec::new(),
+            current_ocr_texts: Vec::new(),
+            current_course_missed: false,
             created_at: chrono::Local::now(),
         }
     }
 
     pub fn set_current_course(&mut self, course: Course) {
         self.current_course = Some(course);
+        self.current_candidates.clear();
+        self.current_ocr_texts.clear();
+        self.current_course_missed = false;
+    }
+
+    /// 手動でコースを直す。直したことを覚えるために、OCRの文字列は残す
+    pub fn correct_current_course(&mut self, course: Course) {
+        self.current_course = Some(course);
+        self.current_candidates.clear();
+        self.current_course_missed = false;
+    }
+
+    /// 次の順位はコースがわからないまま記録する。あとからGUIで直す
+    pub fn mark_current_course_missed(&mut self) {
+        if self.current_course.is_none() {
+            self.current_course_missed = true;
+        }
+    }
+
+    pub fn set_current_candidates(&mut self, candidates: Vec<Course>) {
+        self.current_candidates = candidates;
+    }
+
+    pub fn set_current_ocr_texts(&mut self, ocr_texts: Vec<String>) {
+        self.current_ocr_texts = ocr_texts;
     }
 
     pub fn set_current_position(&mut self, position: Position) {
-        let current_course = self.current_course.clone();
-        if let Some(current_course) = current_course {
-            let race = RaceResult::new(Some(current_course), position);
-            self.races.push(race);
-            self.current_course = None;
+        if self.current_course.is_none() && !self.current_course_missed {
+            return;
         }
+        let mut race = RaceResult::new(self.current_course.take(), position);
+        race.set_candidates(std::mem::take(&mut self.current_candidates));
+        race.set_ocr_texts(std::mem::take(&mut self.current_ocr_texts));
+        self.races.push(race);
+        self.current_course_missed = false;
     }
 
     pub fn reset_current_course(&mut self) {
         self.current_course = None;
+        self.current_candidates.clear();
+        self.current_ocr_texts.clear();
+        self.current_course_missed = false;
     }
 
     pub fn iter_races(&self) -> std::slice::Iter<RaceResult> {
@@ -49,6 +91,14 @@ impl MogiResult {
         &self.current_course
     }
 
+    pub fn current_candidates(&self) -> &[Course] {
+        &self.current_candidates
+    }
+
+    pub fn current_ocr_texts(&self) -> &[String] {
+        &self.current_ocr_texts
+    }
+
     pub fn set_course(&mut self, index: usize, course: Course) {
         self.races[index].set_course(course);
     }
@@ -57,6 +107,13 @@ impl MogiResult {
         self.races[index].set_position(position);
     }
 
+    /// 表の読み取りが終わる前に消されたレースは無視する
+    pub fn set_table(&mut self, index: usize, table: Vec<ResultsRow>) {
+        if let Some(race) = self.races.get_mut(index) {
+            race.set_table(table);
+        }
+    }
+
     pub fn total_score(&self) -> u32 {
         self.races.iter().map(|r| r.to_score()).sum::<u32>()
     }
@@ -134,4 +191,24 @@ mod tests {
         mogi_result.reset_current_course();
         assert_eq!(mogi_result.current_course, None);
     }
+
+    #[test]
+    fn test_mogi_result_candidates() {
+        let guessed = Course::new("ドルフィンみさき".to_string(), Console::New);
+        let candidate = Course::new("ドーナツへいや3".to_string(), Console::SFC);
+        let mut mogi_result = MogiResult::new();
+        mogi_result.set_current_course(guessed.clone());
+        mogi_result.set_current_candidates(vec![candidate.clone()]);
+        // 順位が決まったら、候補もレースの結果に移る
+        mogi_result.set_current_position(Position::First);
+        assert!(mogi_result.current_candidates().is_empty());
+        let race = mogi_result.iter_races().next().unwrap();
+        assert_eq!(race.candidates(), std::slice::from_ref(&candidate));
+
+        // 候補から選び直したら、候補は消える
+        mogi_result.set_course(0, candidate.clone());
+        let race = mogi_result.iter_races().next().unwrap();
+        assert_eq!(race.course(), Some(candidate));
+        assert!(race.candidates().is_empty());
+    }
 }
@@ -0,0 +1,130 @@
+use unicode_normalization::UnicodeNormalization;
+
+// 濁点・半濁点(結合文字)
+const COMBINING_VOICED_MARKS: [char; 2] = ['\u{3099}', '\u{309A}'];
+// 単体の濁点・半濁点。NFKDで空白と結合文字に分解されるので、先に取り除く
+const SPACING_VOICED_MARKS: [char; 2] = ['゛', '゜'];
+
+// ひらがなの範囲。カタカナとの差は0x60
+const HIRAGANA: std::ops::RangeInclusive<char> = 'ぁ'..='ゖ';
+const HIRAGANA_TO_KATAKANA: u32 = 0x60;
+
+// カタカナの小文字を大文字にする
+const SMALL_KANA: [(char, char); 12] = [
+    ('ァ', 'ア'),
+    ('ィ', 'イ'),
+    ('ゥ', 'ウ'),
+    ('ェ', 'エ'),
+    ('ォ', 'オ'),
+    ('ッ', 'ツ'),
+    ('ャ', 'ヤ'),
+    ('ュ', 'ユ'),
+    ('ョ', 'ヨ'),
+    ('ヮ', 'ワ'),
+    ('ヵ', 'カ'),
+    ('ヶ', 'ケ'),
+];
+
+// 長音記号と見分けがつかないもの。全角の－はNFKDで-になる
+const LONG_VOWEL_MARKS: [char; 8] = [
+    '-', '\u{2010}', // ‐
+    '\u{2012}', // ‒
+    '\u{2013}', // –
+    '\u{2014}', // —
+    '\u{2015}', // ―
+    '\u{2212}', // −
+    '一',       // OCRでよく長音記号と間違える
+];
+
+// OCRでよく間違える文字
+const LOOKALIKES: [(char, char); 1] = [('工', 'エ')];
+
+/// OCRの結果とコース名、GUIの入力を比べられるようにそろえる
+///
+/// 1. NFKDで分解する(全角英数字は半角に、半角カナは全角に、濁点つきの文字は濁点と分ける)
+/// 2. 濁点・半濁点を取り除く
+/// 3. ひらがなをカタカナに、カタカナの小文字を大文字にする
+/// 4. 長音記号に似た文字を「ー」に、工を「エ」にする
+/// 5. 英字を小文字にする
+pub fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let chars = text
+        .chars()
+        .filter(|c| !SPACING_VOICED_MARKS.contains(c))
+        .nfkd()
+        .filter(|c| !COMBINING_VOICED_MARKS.contains(c));
+    for c in chars {
+        normalized.extend(fold(c).to_lowercase());
+    }
+    normalized
+}
+
+fn fold(c: char) -> char {
+    let c = if HIRAGANA.contains(&c) {
+        char::from_u32(c as u32 + HIRAGANA_TO_KATAKANA).unwrap_or(c)
+    } else {
+        c
+    };
+    if let Some((_, large)) = SMALL_KANA.iter().find(|(small, _)| *small == c) {
+        return *large;
+    }
+    if LONG_VOWEL_MARKS.contains(&c) {
+        return 'ー';
+    }
+    if let Some((_, to)) = LOOKALIKES.iter().find(|(from, _)| *from == c) {
+        return *to;
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("あがぱ工EｅＥ"), "アカハエeee");
+        assert_eq!(normalize("ﾄﾞｯｽﾝいせき"), "トツスンイセキ");
+        assert_eq!(normalize("ヨッシ一サ－キット"), "ヨツシーサーキツト");
+        assert_eq!(normalize("ｳｫｰﾀｰﾊﾟｰｸ"), normalize("ウォーターパーク"));
+        assert_eq!(normalize("ヴ゛ぉ"), "ウオ");
+        assert_eq!(normalize("ＤＫ　ジャングル"), "dk シヤンクル");
+    }
+
+    proptest! {
+        #[test]
+        fn normalize_is_idempotent(s in "\\PC*") {
+            let once = normalize(&s);
+            prop_assert_eq!(normalize(&once), once);
+        }
+
+        #[test]
+        fn hiragana_and_katakana_are_same(s in "[ぁ-ゖ]*") {
+            let katakana = s
+                .chars()
+                .map(|c| char::from_u32(c as u32 + HIRAGANA_TO_KATAKANA).unwrap())
+                .collect::<String>();
+            prop_assert_eq!(normalize(&s), normalize(&katakana));
+        }
+
+        #[test]
+        fn full_width_and_ascii_are_same(s in "[0-9A-Za-z]*") {
+            // 全角英数字は半角から0xFEE0ずれている
+            let full_width = s
+                .chars()
+                .map(|c| char::from_u32(c as u32 + 0xFEE0).unwrap())
+                .collect::<String>();
+            prop_assert_eq!(normalize(&full_width), s.to_lowercase());
+        }
+
+        #[test]
+        fn no_voiced_marks_or_hiragana_left(s in "[ぁ-ゖァ-ヺ゙-゜ｦ-ﾟ]*") {
+            let normalized = normalize(&s);
+            prop_assert!(!normalized.chars().any(|c| COMBINING_VOICED_MARKS.contains(&c)));
+            prop_assert!(!normalized.chars().any(|c| SPACING_VOICED_MARKS.contains(&c)));
+            prop_assert!(!normalized.chars().any(|c| HIRAGANA.contains(&c)));
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use crate::word::Word;
+
+use super::Ocr;
+
+/// 1回の認識結果。フィクスチャファイルには1行に1つずつJSONで書く
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixtureEntry {
+    // 何回目の認識か
+    pub index: usize,
+    // 認識した画像のハッシュ
+    pub hash: String,
+    pub words: Vec<Word>,
+}
+
+/// 画像の大きさと画素からハッシュを作る (FNV-1a)
+pub fn image_hash(image: &RgbImage) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let size = [image.width().to_le_bytes(), image.height().to_le_bytes()];
+    for byte in size.iter().flatten().chain(image.as_raw()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// 記録した認識結果を再生する。実機のOCRがない環境でのテスト用
+/// 同じ画像の結果があればそれを返し、なければ同じ順番で認識した結果を返す
+pub struct FixtureOcr {
+    entries: Vec<FixtureEntry>,
+    count: AtomicUsize,
+}
+
+impl FixtureOcr {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<FixtureOcr> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        FixtureOcr::from_json_lines(&text)
+    }
+
+    pub fn from_json_lines(text: &str) -> anyhow::Result<FixtureOcr> {
+        let entries = text
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<FixtureEntry>, _>>()?;
+        Ok(FixtureOcr::new(entries))
+    }
+
+    pub fn new(entries: Vec<FixtureEntry>) -> FixtureOcr {
+        FixtureOcr {
+            entries,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    fn find(&self, hash: &str, index: usize) -> Option<&FixtureEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.hash == hash)
+            .or_else(|| self.entries.iter().find(|e| e.index == index))
+    }
+}
+
+#[async_trait]
+impl Ocr for FixtureOcr {
+    async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+        let index = self.count.fetch_add(1, Ordering::Relaxed);
+        let hash = image_hash(image);
+        match self.find(&hash, index) {
+            Some(entry) => Ok(entry.words.clone()),
+            None => {
+                log::warn!("no fixture for index {} (hash {})", index, hash);
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// 実際のOCRの結果をフィクスチャの形式で追記していく
+pub struct RecordingOcr {
+    inner: Box<dyn Ocr>,
+    path: PathBuf,
+    count: AtomicUsize,
+}
+
+impl RecordingOcr {
+    pub fn new(inner: Box<dyn Ocr>, path: impl Into<PathBuf>) -> RecordingOcr {
+        let path = path.into();
+        log::info!("recording ocr results to {}", path.display());
+        RecordingOcr {
+            inner,
+            path,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    fn append(&self, entry: &FixtureEntry) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Ocr for RecordingOcr {
+    async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+        let words = self.inner.recognize(image).await?;
+        let entry = FixtureEntry {
+            index: self.count.fetch_add(1, Ordering::Relaxed),
+            hash: image_hash(image),
+            words,
+        };
+        // 記録に失敗しても認識は続ける
+        if let Err(e) = self.append(&entry) {
+            log::error!("failed to record ocr result: {:?}", e);
+        }
+        Ok(entry.words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 画像の左上の画素の値を単語として返す
+    struct PixelOcr;
+
+    #[async_trait]
+    impl Ocr for PixelOcr {
+        async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+            let text = image.get_pixel(0, 0).0[0].to_string();
+            Ok(vec![Word::new(text, 0.0, 0.0, 10.0, 10.0)])
+        }
+    }
+
+    fn image(value: u8) -> RgbImage {
+        RgbImage::from_pixel(4, 4, image::Rgb([value, value, value]))
+    }
+
+    #[test]
+    fn test_image_hash() {
+        assert_eq!(image_hash(&image(0)), image_hash(&image(0)));
+        assert_ne!(image_hash(&image(0)), image_hash(&image(1)));
+        // 画素が同じでも大きさが違えば別の画像
+        assert_ne!(
+            image_hash(&RgbImage::new(2, 8)),
+            image_hash(&RgbImage::new(8, 2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixture_lookup() -> anyhow::Result<()> {
+        let fixture = [
+            format!(
+                r#"{{"index":0,"hash":"{}","words":[{{"text":"by hash","x":0.0,"y":0.0,"height":1.0,"width":1.0}}]}}"#,
+                image_hash(&image(9))
+            ),
+            r#"{"index":1,"hash":"","words":[{"text":"by index","x":0.0,"y":0.0,"height":1.0,"width":1.0}]}"#.to_string(),
+        ]
+        .join("\n");
+        let ocr = FixtureOcr::from_json_lines(&fixture)?;
+        // 1回目: ハッシュが一致するものを返す
+        assert_eq!(ocr.recognize(&image(9)).await?[0].text, "by hash");
+        // 2回目: ハッシュが一致しないので、2番目に認識した結果を返す
+        assert_eq!(ocr.recognize(&image(1)).await?[0].text, "by index");
+        // 3回目: どちらもないので何も返さない
+        assert!(ocr.recognize(&image(2)).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "lounge-memo-test-ocr-fixture-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let recording = RecordingOcr::new(Box::new(PixelOcr), &path);
+        let recorded = [
+            recording.recognize(&image(3)).await?,
+            recording.recognize(&image(5)).await?,
+        ];
+
+        // 記録したときと逆の順番でもハッシュで引ける
+        let fixture = FixtureOcr::load(&path)?;
+        assert_eq!(fixture.recognize(&image(5)).await?, recorded[1]);
+        assert_eq!(fixture.recognize(&image(3)).await?, recorded[0]);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use image::RgbImage;
+
+use crate::settings::{OcrBackend, Settings};
+use crate::word::Word;
+
+mod fixture;
+mod pool;
+#[cfg(feature = "tesseract")]
+mod tesseract;
+#[cfg(windows)]
+mod winrt;
+
+#[cfg(feature = "tesseract")]
+pub use self::tesseract::TesseractOcr;
+pub use fixture::{FixtureEntry, FixtureOcr, RecordingOcr};
+pub use pool::{OcrPool, OcrTicket};
+#[cfg(windows)]
+pub use winrt::WinRtOcr;
+
+/// 画像上の矩形。座標はフレーム(WIDTH x HEIGHT)上のピクセル
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// 画像から文字を認識する
+#[async_trait]
+pub trait Ocr: Send + Sync {
+    /// 認識した単語と、単語をつなげた行を返す
+    async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>>;
+
+    /// `region` の範囲だけを認識する。単語の座標は元の画像上の座標に戻して返す
+    async fn recognize_region(&self, image: &RgbImage, region: Rect) -> anyhow::Result<Vec<Word>> {
+        // 画像からはみ出す部分は切り詰められる
+        let cropped =
+            image::imageops::crop_imm(image, region.x, region.y, region.width, region.height)
+                .to_image();
+        let mut words = self.recognize(&cropped).await?;
+        for word in words.iter_mut() {
+            word.x += region.x as f64;
+            word.y += region.y as f64;
+        }
+        Ok(words)
+    }
+}
+
+/// OCRが使えない環境用。何も認識しない
+pub struct NoOcr;
+
+#[async_trait]
+impl Ocr for NoOcr {
+    async fn recognize(&self, _image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+        static WARN_ONCE: std::sync::Once = std::sync::Once::new();
+        WARN_ONCE.call_once(|| log::warn!("OCR is not available, nothing will be recognized"));
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(windows)]
+fn create_winrt_ocr() -> anyhow::Result<Box<dyn Ocr>> {
+    Ok(Box::new(WinRtOcr::new()?))
+}
+
+#[cfg(not(windows))]
+fn create_winrt_ocr() -> anyhow::Result<Box<dyn Ocr>> {
+    Err(anyhow::anyhow!(
+        "Windows.Media.Ocr is only supported on Windows"
+    ))
+}
+
+#[cfg(feature = "tesseract")]
+fn create_tesseract_ocr() -> anyhow::Result<Box<dyn Ocr>> {
+    Ok(Box::new(TesseractOcr::new()?))
+}
+
+#[cfg(not(feature = "tesseract"))]
+fn create_tesseract_ocr() -> anyhow::Result<Box<dyn Ocr>> {
+    Err(anyhow::anyhow!(
+        "tesseract is not enabled, build with `--features tesseract`"
+    ))
+}
+
+fn try_create_ocr(settings: &Settings) -> anyhow::Result<Box<dyn Ocr>> {
+    let ocr = match settings.ocr_backend() {
+        OcrBackend::Auto if cfg!(windows) => create_winrt_ocr()?,
+        OcrBackend::Auto => create_tesseract_ocr()?,
+        OcrBackend::WinRt => create_winrt_ocr()?,
+        OcrBackend::Tesseract => create_tesseract_ocr()?,
+        // 記録したものを再生するので、記録はしない
+        OcrBackend::Fixture => return Ok(Box::new(FixtureOcr::load(settings.ocr_fixture_path())?)),
+    };
+    if settings.record_ocr() {
+        return Ok(Box::new(RecordingOcr::new(
+            ocr,
+            settings.ocr_fixture_path(),
+        )));
+    }
+    Ok(ocr)
+}
+
+/// 設定されたOCRを作る。作れなかった場合は何も認識しないOCRを返す
+pub fn create_ocr(settings: &Settings) -> Box<dyn Ocr> {
+    match try_create_ocr(settings) {
+        Ok(ocr) => ocr,
+        Err(e) => {
+            log::error!(
+                "failed to create ocr ({:?}): {:?}",
+                settings.ocr_backend(),
+                e
+            );
+            Box::new(NoOcr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 渡された画像の大きさを、左上から(1, 2)の位置にある単語として返す
+    struct SizeOcr;
+
+    #[async_trait]
+    impl Ocr for SizeOcr {
+        async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+            Ok(vec![Word::new(
+                format!("{}x{}", image.width(), image.height()),
+                1.0,
+                2.0,
+                10.0,
+                10.0,
+            )])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recognize_region() -> anyhow::Result<()> {
+        let image = RgbImage::new(1280, 720);
+        let words = SizeOcr
+            .recognize_region(&image, Rect::new(100, 600, 400, 120))
+            .await?;
+        assert_eq!(words[0].text, "400x120");
+        assert_eq!((words[0].x, words[0].y), (101.0, 602.0));
+
+        // はみ出した部分は切り詰める
+        let words = SizeOcr
+            .recognize_region(&image, Rect::new(1200, 700, 400, 120))
+            .await?;
+        assert_eq!(words[0].text, "80x20");
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+
+use crate::frame::Frame;
+use crate::word::Word;
+
+use super::{Ocr, Rect};
+
+// 認識待ちの数。これを超えたら新しい要求は失敗させる
+const QUEUE_SIZE: usize = 16;
+// 同じ画像かどうかを調べるために覚えておく要求の数
+const RECENT_JOBS: usize = 16;
+
+// Noneは認識中。エラーは複数のチケットで共有するので文字列にしておく
+type Outcome = Option<Result<Vec<Word>, String>>;
+
+struct Job {
+    frame: Frame,
+    region: Rect,
+    result_tx: Arc<watch::Sender<Outcome>>,
+}
+
+/// 認識の要求。結果はあとから `try_result` で受け取る
+/// 同じ要求のチケットがすべて破棄されると、認識はキャンセルされる
+#[derive(Clone)]
+pub struct OcrTicket {
+    rx: watch::Receiver<Outcome>,
+}
+
+impl OcrTicket {
+    fn failed(message: String) -> OcrTicket {
+        let (_, rx) = watch::channel(Some(Err(message)));
+        OcrTicket { rx }
+    }
+
+    /// 認識が終わっていれば結果を返す。まだの場合はNone
+    pub fn try_result(&self) -> Option<anyhow::Result<Vec<Word>>> {
+        self.rx.borrow().clone().map(to_anyhow)
+    }
+
+    /// 認識が終わるまで待つ
+    #[allow(dead_code)]
+    pub async fn result(mut self) -> anyhow::Result<Vec<Word>> {
+        let outcome = self.rx.wait_for(|o| o.is_some()).await?.clone();
+        to_anyhow(outcome.unwrap())
+    }
+}
+
+fn to_anyhow(result: Result<Vec<Word>, String>) -> anyhow::Result<Vec<Word>> {
+    result.map_err(|e| anyhow::anyhow!(e))
+}
+
+/// OCRを別のタスクで動かす。Detectorは要求を出したら、結果を待たずに次のフレームを処理できる
+pub struct OcrPool {
+    queue: mpsc::Sender<Job>,
+    recent: Mutex<VecDeque<(u64, Arc<watch::Sender<Outcome>>)>>,
+}
+
+impl OcrPool {
+    /// tokioのランタイム上で呼ぶ
+    pub fn new(ocr: Box<dyn Ocr>, workers: usize, timeout: Duration) -> OcrPool {
+        let ocr: Arc<dyn Ocr> = Arc::from(ocr);
+        let (queue, rx) = mpsc::channel(QUEUE_SIZE);
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        for _ in 0..workers.max(1) {
+            tokio::spawn(work(ocr.clone(), rx.clone(), timeout));
+        }
+        OcrPool {
+            queue,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// `frame` の `region` の範囲の認識を要求する
+    /// 同じ画像を認識中か、認識し終わったばかりの場合はその結果を使う
+    pub fn submit(&self, frame: &Frame, region: Rect) -> OcrTicket {
+        let key = region_hash(frame, region);
+        let mut recent = self.recent.lock().unwrap();
+        let same = recent.iter().find(|(k, tx)| {
+            // 失敗したものは使い回さない
+            *k == key && !matches!(*tx.borrow(), Some(Err(_)))
+        });
+        if let Some((_, tx)) = same {
+            log::trace!("ocr: reuse result for the same image");
+            return OcrTicket { rx: tx.subscribe() };
+        }
+
+        let (result_tx, rx) = watch::channel(None);
+        let result_tx = Arc::new(result_tx);
+        let job = Job {
+            frame: frame.clone(),
+            region,
+            result_tx: result_tx.clone(),
+        };
+        if let Err(e) = self.queue.try_send(job) {
+            log::warn!("ocr: failed to queue request: {}", e);
+            return OcrTicket::failed(format!("failed to queue ocr request: {}", e));
+        }
+        recent.push_back((key, result_tx));
+        if recent.len() > RECENT_JOBS {
+            recent.pop_front();
+        }
+        OcrTicket { rx }
+    }
+}
+
+async fn work(
+    ocr: Arc<dyn Ocr>,
+    queue: Arc<tokio::sync::Mutex<mpsc::Receiver<Job>>>,
+    timeout: Duration,
+) {
+    loop {
+        let job = match queue.lock().await.recv().await {
+            Some(job) => job,
+            None => return,
+        };
+        // 待っている間にチケットがすべて捨てられた
+        if job.result_tx.receiver_count() == 0 {
+            log::trace!("ocr: cancelled before start");
+            job.result_tx
+                .send_replace(Some(Err("cancelled".to_string())));
+            continue;
+        }
+        let recognize = ocr.recognize_region(job.frame.image(), job.region);
+        let outcome = tokio::select! {
+            result = tokio::time::timeout(timeout, recognize) => match result {
+                Ok(Ok(words)) => Ok(words),
+                Ok(Err(e)) => Err(format!("{:?}", e)),
+                Err(_) => Err(format!("ocr timed out after {:?}", timeout)),
+            },
+            _ = job.result_tx.closed() => {
+                log::trace!("ocr: cancelled while recognizing");
+                Err("cancelled".to_string())
+            }
+        };
+        if let Err(e) = &outcome {
+            log::debug!("ocr: {}", e);
+        }
+        job.result_tx.send_replace(Some(outcome));
+    }
+}
+
+// 要求された範囲の画素だけからハッシュを作る (FNV-1a)
+fn region_hash(frame: &Frame, region: Rect) -> u64 {
+    let image = frame.image();
+    let right = (region.x + region.width).min(image.width());
+    let bottom = (region.y + region.height).min(image.height());
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut write = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+    for b in [region.x, region.y, region.width, region.height]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+    {
+        write(b);
+    }
+    let raw = image.as_raw();
+    let stride = image.width() as usize * 3;
+    for y in region.y.min(bottom)..bottom {
+        let start = y as usize * stride + region.x.min(right) as usize * 3;
+        let end = y as usize * stride + right as usize * 3;
+        raw[start..end].iter().for_each(|b| write(*b));
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use image::RgbImage;
+
+    use super::*;
+
+    // 呼ばれた回数を数える。`delay` だけ待ってから返す
+    struct SlowOcr {
+        calls: Arc<AtomicUsize>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl Ocr for SlowOcr {
+        async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            let text = image.get_pixel(0, 0).0[0].to_string();
+            Ok(vec![Word::new(text, 0.0, 0.0, 1.0, 1.0)])
+        }
+    }
+
+    fn pool(delay: Duration, timeout: Duration) -> (OcrPool, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let ocr = SlowOcr {
+            calls: calls.clone(),
+            delay,
+        };
+        (OcrPool::new(Box::new(ocr), 2, timeout), calls)
+    }
+
+    fn frame(value: u8) -> Frame {
+        let image = RgbImage::from_pixel(8, 8, image::Rgb([value, value, value]));
+        Frame::new(image, Duration::ZERO, 0, "test".into())
+    }
+
+    const REGION: Rect = Rect::new(0, 0, 4, 4);
+
+    #[tokio::test]
+    async fn test_submit() -> anyhow::Result<()> {
+        let (pool, calls) = pool(Duration::from_millis(50), Duration::from_secs(5));
+        let ticket = pool.submit(&frame(7), REGION);
+        // 待たずに返る
+        assert!(ticket.try_result().is_none());
+        // 同じ画像は一度しか認識しない
+        let same = pool.submit(&frame(7), REGION);
+        assert_eq!(ticket.result().await?[0].text, "7");
+        assert_eq!(same.result().await?[0].text, "7");
+        assert_eq!(pool.submit(&frame(7), REGION).result().await?[0].text, "7");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(pool.submit(&frame(8), REGION).result().await?[0].text, "8");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (pool, _) = pool(Duration::from_secs(10), Duration::from_millis(10));
+        let result = pool.submit(&frame(1), REGION).result().await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel() -> anyhow::Result<()> {
+        let (pool, calls) = pool(Duration::from_millis(200), Duration::from_secs(5));
+        // 2つのワーカーが認識中の間に、3つ目を要求してすぐに捨てる
+        let first = pool.submit(&frame(1), REGION);
+        let second = pool.submit(&frame(2), REGION);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(pool.submit(&frame(3), REGION));
+        first.result().await?;
+        second.result().await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // キャンセルされたものは使い回さずに認識し直す
+        assert_eq!(pool.submit(&frame(3), REGION).result().await?[0].text, "3");
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::{Arc, Mutex};
+
+use ::tesseract::Tesseract;
+use async_trait::async_trait;
+use image::RgbImage;
+
+use crate::word::Word;
+
+use super::Ocr;
+
+// コース名にはSFCやGBAなどの英字も含まれるので、英語も読ませる
+const LANGUAGE: &str = "jpn+eng";
+// 単語単位の行
+const WORD_LEVEL: &str = "5";
+
+// 学習データは TESSDATA_PREFIX から読み込む
+fn init_tesseract() -> anyhow::Result<Tesseract> {
+    Ok(Tesseract::new(None, Some(LANGUAGE))?)
+}
+
+/// Tesseractを使う。学習データ(jpn, eng)が必要
+pub struct TesseractOcr {
+    // Tesseractのメソッドはselfを消費するので、使うときに取り出して戻す
+    api: Arc<Mutex<Option<Tesseract>>>,
+}
+
+impl TesseractOcr {
+    pub fn new() -> anyhow::Result<TesseractOcr> {
+        let api = init_tesseract()?;
+        Ok(TesseractOcr {
+            api: Arc::new(Mutex::new(Some(api))),
+        })
+    }
+}
+
+fn recognize_tsv(api: &Mutex<Option<Tesseract>>, image: &RgbImage) -> anyhow::Result<String> {
+    let mut api = api.lock().unwrap();
+    let tess = match api.take() {
+        Some(tess) => tess,
+        // 前回失敗したときは作り直す
+        None => init_tesseract()?,
+    };
+    let width = image.width() as i32;
+    let height = image.height() as i32;
+    let mut tess = tess
+        .set_frame(image.as_raw(), width, height, 3, width * 3)?
+        .recognize()?;
+    let tsv = tess.get_tsv_text(0)?;
+    *api = Some(tess);
+    Ok(tsv)
+}
+
+#[async_trait]
+impl Ocr for TesseractOcr {
+    async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+        let api = self.api.clone();
+        let image = image.clone();
+        // 認識には時間がかかるので、asyncのスレッドを止めないようにする
+        let tsv = tokio::task::spawn_blocking(move || recognize_tsv(&api, &image)).await??;
+        Ok(words_from_tsv(&tsv))
+    }
+}
+
+// TSVの各単語と、Windows.Media.Ocrと同じように単語を空白なしでつなげた行を返す
+// 列: level page_num block_num par_num line_num word_num left top width height conf text
+fn words_from_tsv(tsv: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut line_words: Vec<Word> = Vec::new();
+    let mut line_key = None;
+    let mut line = 0;
+    for row in tsv.lines() {
+        let columns = row.split('\t').collect::<Vec<&str>>();
+        if columns.len() < 12 || columns[0] != WORD_LEVEL {
+            continue;
+        }
+        let text = columns[11].trim();
+        if text.is_empty() {
+            continue;
+        }
+        let parse = |s: &str| s.parse::<f64>().unwrap_or_default();
+        let key = (columns[2], columns[3], columns[4]);
+        if line_key != Some(key) {
+            // 行が変わったら、前の行をつなげたものを追加する
+            if line_key.is_some() {
+                words.extend(Word::join_line(&line_words));
+                line_words.clear();
+                line += 1;
+            }
+            line_key = Some(key);
+        }
+        // 認識できなかった場合は-1になる
+        let confidence = columns[10]
+            .parse::<f32>()
+            .ok()
+            .filter(|c| *c >= 0.0)
+            .map(|c| c / 100.0);
+        let word = Word {
+            line,
+            confidence,
+            ..Word::new(
+                text.to_string(),
+                parse(columns[6]),
+                parse(columns[7]),
+                parse(columns[9]),
+                parse(columns[8]),
+            )
+        };
+        words.push(word.clone());
+        line_words.push(word);
+    }
+    words.extend(Word::join_line(&line_words));
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::word::WordKind;
+
+    use super::*;
+
+    #[test]
+    fn test_words_from_tsv() {
+        let tsv = [
+            "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext",
+            "1\t1\t0\t0\t0\t0\t0\t0\t1280\t720\t-1\t",
+            "4\t1\t1\t1\t1\t0\t100\t650\t200\t30\t-1\t",
+            "5\t1\t1\t1\t1\t1\t100\t650\t60\t30\t91.5\tSFC",
+            "5\t1\t1\t1\t1\t2\t170\t652\t130\t28\t88.0\tマリオサーキット",
+            "5\t1\t2\t1\t1\t1\t10\t10\t50\t20\t95.0\t12:00",
+        ]
+        .join("\n");
+        let words = words_from_tsv(&tsv);
+        let texts = words.iter().map(|w| w.text.as_str()).collect::<Vec<&str>>();
+        assert_eq!(
+            texts,
+            vec![
+                "SFC",
+                "マリオサーキット",
+                "SFCマリオサーキット",
+                "12:00",
+                "12:00"
+            ]
+        );
+        let line = &words[2];
+        assert_eq!(
+            (line.x, line.y, line.width, line.height),
+            (100.0, 650.0, 200.0, 30.0)
+        );
+        assert_eq!(line.kind, WordKind::Line);
+        assert_eq!(line.confidence, Some(0.88));
+        assert_eq!((words[0].line, words[3].line), (0, 1));
+    }
+}
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use image::RgbImage;
+use windows::{
+    core::Interface,
+    Graphics::Imaging::{BitmapBufferAccessMode, BitmapPixelFormat, SoftwareBitmap},
+    Media::Ocr::OcrEngine,
+    Win32::System::WinRT::IMemoryBufferByteAccess,
+};
+
+use crate::word::Word;
+
+use super::Ocr;
+
+fn make_bmp(buffer: &[u8], width: i32, height: i32) -> anyhow::Result<SoftwareBitmap> {
+    let bmp = SoftwareBitmap::Create(BitmapPixelFormat::Rgba8, width, height)?;
+    {
+        let bmp_buf = bmp.LockBuffer(BitmapBufferAccessMode::ReadWrite)?;
+        let array: IMemoryBufferByteAccess = bmp_buf.CreateReference()?.cast()?;
+
+        let mut data = std::ptr::null_mut();
+        let mut capacity = 0;
+        unsafe {
+            array.GetBuffer(&mut data, &mut capacity)?;
+        }
+        assert_eq!((width * height * 4).abs(), capacity as i32);
+
+        let slice = unsafe { std::slice::from_raw_parts_mut(data, capacity as usize) };
+        slice.chunks_mut(4).enumerate().for_each(|(i, c)| {
+            c[0] = buffer[3 * i];
+            c[1] = buffer[3 * i + 1];
+            c[2] = buffer[3 * i + 2];
+            c[3] = 255;
+        });
+    }
+
+    Ok(bmp)
+}
+
+/// Windows.Media.Ocr を使う。ユーザーの言語設定に日本語が必要
+pub struct WinRtOcr;
+
+impl WinRtOcr {
+    pub fn new() -> anyhow::Result<WinRtOcr> {
+        // 言語パックがない場合などはここで失敗させる
+        OcrEngine::TryCreateFromUserProfileLanguages()?;
+        Ok(WinRtOcr)
+    }
+}
+
+#[async_trait]
+impl Ocr for WinRtOcr {
+    async fn recognize(&self, image: &RgbImage) -> anyhow::Result<Vec<Word>> {
+        let bmp = make_bmp(image.as_raw(), image.width() as _, image.height() as _)?;
+        let engine = OcrEngine::TryCreateFromUserProfileLanguages()?;
+        let result = engine.RecognizeAsync(&bmp)?.await?.Lines()?;
+        let mut collected_words: Vec<Word> = Vec::new();
+
+        for (line_index, line) in result.into_iter().enumerate() {
+            let mut line_words = Vec::new();
+            for word in line.Words()? {
+                let rect = word.BoundingRect()?;
+                line_words.push(Word {
+                    line: line_index,
+                    ..Word::new(
+                        word.Text()?.to_string_lossy(),
+                        rect.X.into(),
+                        rect.Y.into(),
+                        rect.Height.into(),
+                        rect.Width.into(),
+                    )
+                });
+            }
+            // 行のテキストは単語を空白でつないだものなので、空白なしでつなげ直す
+            let line = Word::join_line(&line_words);
+            collected_words.extend(line_words);
+            collected_words.extend(line);
+        }
+        Ok(collected_words)
+    }
+}
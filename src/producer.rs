@@ -1,53 +1,106 @@
 use std::{fs::File, io::Write};
 
-use image::RgbImage;
+use image::{imageops::FilterType, RgbImage};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
-    capture::{Capture, DirectShowCapture, MSMFCapture},
+    capture::{CaptureStatus, CaptureSupervisor},
+    frame_slot::FrameSender,
     settings::Settings,
 };
 
+// GUIのプレビュー用に縮小する幅
+const PREVIEW_WIDTH: u32 = 480;
+// 設定やDetectorの要求がこれより低くても、このfpsは保つ
+const MIN_FRAME_RATE: f64 = 1.0;
+
+// 設定のfpsを上限として、Detectorが要求したfpsを使う
+fn effective_frame_rate(settings_rate: f64, requested: Option<f64>) -> f64 {
+    let rate = match requested {
+        Some(requested) => requested.min(settings_rate),
+        None => settings_rate,
+    };
+    // NaNの場合もMIN_FRAME_RATEになる
+    rate.max(MIN_FRAME_RATE)
+}
+
+fn preview_image(img: &RgbImage) -> RgbImage {
+    let height = PREVIEW_WIDTH * img.height() / img.width().max(1);
+    image::imageops::resize(img, PREVIEW_WIDTH, height, FilterType::Triangle)
+}
+
 #[derive(Debug)]
 pub struct Producer;
 
 impl Producer {
     pub async fn run(
         &mut self,
-        tx: Sender<RgbImage>,
+        tx: FrameSender,
         mut settings_rx: Receiver<Settings>,
+        status_tx: Sender<CaptureStatus>,
+        preview_tx: Sender<RgbImage>,
+        mut frame_rate_rx: Receiver<Option<f64>>,
     ) -> anyhow::Result<()> {
         log::info!("producer");
-        let mut capture: Option<Box<dyn Capture>> = None;
+        let mut supervisor: Option<CaptureSupervisor> = None;
+        let mut last_status = CaptureStatus::Disconnected;
+        let mut settings_rate = MIN_FRAME_RATE;
+        let mut requested_rate = None;
         loop {
+            while let Ok(rate) = frame_rate_rx.try_recv() {
+                requested_rate = rate;
+            }
             if let Ok(new_settings) = settings_rx.try_recv() {
-                if capture.is_some() {
-                    let _ = capture.take();
-                }
-                capture = if new_settings.directshow() {
-                    DirectShowCapture::new(new_settings.device_name())
-                        .map(|c| Box::new(c) as Box<dyn Capture>)
-                        .map_err(|e| log::error!("DirectShowCapture creation failed: {:?}", e))
-                        .ok()
-                } else {
-                    MSMFCapture::new(new_settings.device_name())
-                        .map(|c| Box::new(c) as Box<dyn Capture>)
-                        .map_err(|e| log::error!("MSMFCapture creation failed: {:?}", e))
-                        .ok()
-                };
-
-                if capture.is_none() {
-                    continue;
+                settings_rate = new_settings.frame_rate();
+                if supervisor.is_some() {
+                    let _ = supervisor.take();
                 }
+                let new_supervisor = CaptureSupervisor::new(new_settings.clone());
+                let opened = new_supervisor.is_opened();
+                supervisor = Some(new_supervisor);
 
-                let mut file = File::create("settings.toml")?;
-                let toml = toml::to_string_pretty(&new_settings)?;
-                file.write_all(toml.as_bytes())?;
+                if opened {
+                    let mut file = File::create("settings.toml")?;
+                    let toml = toml::to_string_pretty(&new_settings)?;
+                    file.write_all(toml.as_bytes())?;
+                }
             }
-            if let Some(capture) = capture.as_mut() {
-                capture.capture(&tx).await?;
-                capture.sleep().await;
+            if let Some(supervisor) = supervisor.as_mut() {
+                if let Some(frame) = supervisor.capture().await {
+                    // GUIが前のプレビューを受け取っていない間は縮小しない
+                    if preview_tx.capacity() > 0 {
+                        let _ = preview_tx.try_send(preview_image(frame.image()));
+                    }
+                    // 動画ファイルなどは検出結果が変わらないように、1フレームも捨てずに渡す
+                    if supervisor.is_live() {
+                        tx.send(frame)?;
+                    } else {
+                        tx.send_lossless(frame).await?;
+                    }
+                }
+                if supervisor.status() != last_status {
+                    last_status = supervisor.status();
+                    let _ = status_tx.try_send(last_status);
+                }
+                supervisor
+                    .sleep(effective_frame_rate(settings_rate, requested_rate))
+                    .await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_frame_rate() {
+        assert_eq!(effective_frame_rate(30.0, None), 30.0);
+        assert_eq!(effective_frame_rate(30.0, Some(5.0)), 5.0);
+        // 設定より高いfpsは要求できない
+        assert_eq!(effective_frame_rate(15.0, Some(60.0)), 15.0);
+        assert_eq!(effective_frame_rate(0.0, None), MIN_FRAME_RATE);
+        assert_eq!(effective_frame_rate(f64::NAN, None), MIN_FRAME_RATE);
+    }
+}
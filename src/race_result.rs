@@ -8,11 +8,26 @@ use crate::courses::Course;
 pub struct RaceResult {
     course: Option<Course>,
     position: Position,
+    // コースを推測した場合の他の候補
+    #[serde(default)]
+    candidates: Vec<Course>,
+    // コースを探したときのOCRの文字列。手動で直したときに覚えておくため
+    #[serde(default)]
+    ocr_texts: Vec<String>,
+    // リザルト画面の表。読み取れた行だけが入っている
+    #[serde(default)]
+    table: Vec<ResultsRow>,
 }
 
 impl RaceResult {
     pub fn new(course: Option<Course>, position: Position) -> RaceResult {
-        RaceResult { course, position }
+        RaceResult {
+            course,
+            position,
+            candidates: Vec::new(),
+            ocr_texts: Vec::new(),
+            table: Vec::new(),
+        }
     }
 
     pub fn to_score(&self) -> u32 {
@@ -35,8 +50,34 @@ impl RaceResult {
         self.position
     }
 
+    pub fn candidates(&self) -> &[Course] {
+        &self.candidates
+    }
+
+    pub fn set_candidates(&mut self, candidates: Vec<Course>) {
+        self.candidates = candidates;
+    }
+
+    pub fn ocr_texts(&self) -> &[String] {
+        &self.ocr_texts
+    }
+
+    pub fn set_ocr_texts(&mut self, ocr_texts: Vec<String>) {
+        self.ocr_texts = ocr_texts;
+    }
+
+    pub fn table(&self) -> &[ResultsRow] {
+        &self.table
+    }
+
+    pub fn set_table(&mut self, table: Vec<ResultsRow>) {
+        self.table = table;
+    }
+
+    // 手動で直したら、候補はもう必要ない
     pub fn set_course(&mut self, course: Course) {
         self.course = Some(course);
+        self.candidates.clear();
     }
 
     pub fn set_position(&mut self, position: Position) {
@@ -52,6 +93,17 @@ impl Display for RaceResult {
     }
 }
 
+/// リザルト画面の表の1行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultsRow {
+    pub position: Position,
+    pub name: String,
+    // そのレースで獲得した点数
+    pub points: Option<u32>,
+    // 表に表示されている合計点
+    pub total: Option<u32>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Position {
     First,
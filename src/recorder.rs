@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::frame::Frame;
+
+// 検出からこの時間以内に手動で修正されたら、誤検出とみなして保存する
+const AUTO_DUMP_WINDOW: Duration = Duration::from_secs(60);
+// 保持するフレームの合計サイズの上限。1280x720で約390フレーム(30fpsで約13秒)
+const MAX_BYTES: usize = 1024 * 1024 * 1024;
+
+/// バグ報告用に直近のフレームをメモリ上に保持する
+/// `length` が0の場合は何も保持しない
+/// 長さによらず、合計が `MAX_BYTES` を超えたら古いフレームから捨てる
+#[derive(Debug)]
+pub struct FrameRecorder {
+    length: Duration,
+    max_bytes: usize,
+    frames: VecDeque<Frame>,
+    // 保持しているフレームの画素の合計サイズ
+    bytes: usize,
+    // 最後に検出した時点のタイムスタンプとフレーム
+    last_detection: Option<(Duration, Vec<Frame>)>,
+}
+
+impl FrameRecorder {
+    pub fn new(length: Duration) -> FrameRecorder {
+        FrameRecorder::with_max_bytes(length, MAX_BYTES)
+    }
+
+    fn with_max_bytes(length: Duration, max_bytes: usize) -> FrameRecorder {
+        if !length.is_zero() {
+            log::info!(
+                "FrameRecorder: keep last {:?} (up to {} MiB)",
+                length,
+                max_bytes / 1024 / 1024
+            );
+        }
+        FrameRecorder {
+            length,
+            max_bytes,
+            frames: VecDeque::new(),
+            bytes: 0,
+            last_detection: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.length.is_zero()
+    }
+
+    pub fn push(&mut self, frame: &Frame) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.frames.push_back(frame.clone());
+        self.bytes += frame_bytes(frame);
+        // 保持する長さより古いフレームを捨てる。ソースが変わって時間が戻った場合も捨てる
+        // 最新のフレームは上限を超えていても残す
+        while let Some(oldest) = self.frames.front() {
+            if self.frames.len() == 1
+                || (oldest.timestamp() <= frame.timestamp()
+                    && frame.elapsed_since(oldest.timestamp()) <= self.length
+                    && self.bytes <= self.max_bytes)
+            {
+                break;
+            }
+            self.bytes -= frame_bytes(oldest);
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn frames(&self) -> Vec<Frame> {
+        self.frames.iter().cloned().collect()
+    }
+
+    /// Detectorが結果を更新した時に呼ぶ。その時点のフレームを覚えておく
+    pub fn mark_detection(&mut self, at: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.last_detection = Some((at, self.frames()));
+    }
+
+    /// 直前の検出から間もなく手動で修正された場合に、検出時点のフレームを返す
+    pub fn take_recent_detection(&mut self, now: Duration) -> Option<Vec<Frame>> {
+        let (at, frames) = self.last_detection.take()?;
+        if now.saturating_sub(at) <= AUTO_DUMP_WINDOW {
+            Some(frames)
+        } else {
+            None
+        }
+    }
+
+    /// 直近のフレームを別スレッドでrecordings/以下に保存する
+    pub fn dump(&self, reason: &str) {
+        dump_in_background(self.frames(), reason);
+    }
+}
+
+fn frame_bytes(frame: &Frame) -> usize {
+    frame.image().as_raw().len()
+}
+
+pub fn dump_in_background(frames: Vec<Frame>, reason: &str) {
+    if frames.is_empty() {
+        log::warn!("no frames to dump ({})", reason);
+        return;
+    }
+    let dir = PathBuf::from(format!(
+        "recordings/{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S-%3f")
+    ));
+    let reason = reason.to_string();
+    tokio::task::spawn_blocking(move || match save_frames(&dir, &frames, &reason) {
+        Ok(()) => log::info!("dumped {} frames to {}", frames.len(), dir.display()),
+        Err(e) => log::error!("failed to dump frames: {:?}", e),
+    });
+}
+
+/// 連番のPNGと、各フレームの情報を書いたinfo.txtを保存する
+/// 保存したディレクトリは連番画像としてそのまま入力ソースに使える
+pub fn save_frames(dir: &Path, frames: &[Frame], reason: &str) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut info = std::fs::File::create(dir.join("info.txt"))?;
+    writeln!(info, "reason: {}", reason)?;
+    writeln!(info, "file\tsequence\ttimestamp_ms\tsource")?;
+    for (i, frame) in frames.iter().enumerate() {
+        let name = format!("frame_{:06}.png", i);
+        frame.image().save(dir.join(&name))?;
+        writeln!(
+            info,
+            "{}\t{}\t{}\t{}",
+            name,
+            frame.sequence(),
+            frame.timestamp().as_millis(),
+            frame.source_id()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn frame(sequence: u64, millis: u64) -> Frame {
+        Frame::new(
+            RgbImage::new(2, 2),
+            Duration::from_millis(millis),
+            sequence,
+            "test".into(),
+        )
+    }
+
+    #[test]
+    fn test_keeps_last_frames() {
+        let mut recorder = FrameRecorder::new(Duration::from_secs(1));
+        for i in 0..30 {
+            recorder.push(&frame(i, i * 100));
+        }
+        let sequences = recorder
+            .frames()
+            .iter()
+            .map(|f| f.sequence())
+            .collect::<Vec<u64>>();
+        assert_eq!(sequences, (19..30).collect::<Vec<u64>>());
+
+        // ソースが切り替わって時間が戻ったら、古いソースのフレームは捨てる
+        recorder.push(&frame(0, 0));
+        assert_eq!(recorder.frames().len(), 1);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        // 2x2のフレームは12バイト
+        let mut recorder = FrameRecorder::with_max_bytes(Duration::from_secs(60), 12 * 5);
+        for i in 0..30 {
+            recorder.push(&frame(i, i * 100));
+        }
+        let sequences = recorder
+            .frames()
+            .iter()
+            .map(|f| f.sequence())
+            .collect::<Vec<u64>>();
+        assert_eq!(sequences, (25..30).collect::<Vec<u64>>());
+
+        // 1フレームで上限を超えても、最新のフレームは残す
+        let mut recorder = FrameRecorder::with_max_bytes(Duration::from_secs(60), 1);
+        recorder.push(&frame(0, 0));
+        recorder.push(&frame(1, 100));
+        assert_eq!(recorder.frames().len(), 1);
+        assert_eq!(recorder.frames()[0].sequence(), 1);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut recorder = FrameRecorder::new(Duration::ZERO);
+        recorder.push(&frame(0, 0));
+        recorder.mark_detection(Duration::ZERO);
+        assert!(recorder.frames().is_empty());
+        assert!(recorder.take_recent_detection(Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn test_take_recent_detection() {
+        let mut recorder = FrameRecorder::new(Duration::from_secs(10));
+        recorder.push(&frame(0, 0));
+        recorder.mark_detection(Duration::from_secs(1));
+        assert_eq!(
+            recorder
+                .take_recent_detection(Duration::from_secs(5))
+                .map(|f| f.len()),
+            Some(1)
+        );
+        // 一度取り出したら、同じ検出では保存しない
+        assert!(recorder
+            .take_recent_detection(Duration::from_secs(6))
+            .is_none());
+
+        recorder.mark_detection(Duration::from_secs(10));
+        assert!(recorder
+            .take_recent_detection(Duration::from_secs(10) + AUTO_DUMP_WINDOW * 2)
+            .is_none());
+    }
+
+    #[test]
+    fn test_save_frames() -> anyhow::Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("lounge-memo-test-recorder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        save_frames(&dir, &[frame(3, 0), frame(4, 33)], "test")?;
+        assert!(dir.join("frame_000000.png").exists());
+        assert!(dir.join("frame_000001.png").exists());
+        let info = std::fs::read_to_string(dir.join("info.txt"))?;
+        assert!(info.contains("frame_000001.png\t4\t33\ttest"));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
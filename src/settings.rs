@@ -1,11 +1,60 @@
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureSource {
+    #[default]
+    Device,
+    VideoFile,
+    ImageSequence,
+    Network,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OcrBackend {
+    // WindowsではWindows.Media.Ocr、それ以外ではTesseract
+    #[default]
+    Auto,
+    WinRt,
+    Tesseract,
+    // ocr_fixture_path に記録した結果を再生する。テスト用
+    Fixture,
+}
+
+fn default_playback_speed() -> f64 {
+    1.0
+}
+
+fn default_frame_rate() -> f64 {
+    30.0
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     device_name: String,
     directshow: bool,
     log_level: String,
     write_log_to_file: bool,
+    // 古いsettings.tomlにはないので、デフォルト値を使う
+    #[serde(default)]
+    source: CaptureSource,
+    #[serde(default)]
+    source_path: String,
+    #[serde(default = "default_playback_speed")]
+    playback_speed: f64,
+    // キャプチャする最大のfps。Detectorがこれより低いfpsを要求した場合はそちらを使う
+    #[serde(default = "default_frame_rate")]
+    frame_rate: f64,
+    // バグ報告用に直近のフレームを保持する秒数。0の場合は保持しない
+    #[serde(default)]
+    recorder_seconds: u32,
+    #[serde(default)]
+    ocr_backend: OcrBackend,
+    // OCRの結果を記録・再生するファイル
+    #[serde(default)]
+    ocr_fixture_path: String,
+    // trueの場合、OCRの結果を ocr_fixture_path に追記する
+    #[serde(default)]
+    record_ocr: bool,
 }
 
 impl Settings {
@@ -20,6 +69,14 @@ impl Settings {
             directshow,
             log_level,
             write_log_to_file,
+            source: CaptureSource::default(),
+            source_path: String::new(),
+            playback_speed: default_playback_speed(),
+            frame_rate: default_frame_rate(),
+            recorder_seconds: 0,
+            ocr_backend: OcrBackend::default(),
+            ocr_fixture_path: String::new(),
+            record_ocr: false,
         }
     }
 
@@ -38,4 +95,68 @@ impl Settings {
     pub fn write_log_to_file(&self) -> bool {
         self.write_log_to_file
     }
+
+    pub fn source(&self) -> CaptureSource {
+        self.source
+    }
+
+    pub fn source_path(&self) -> &str {
+        &self.source_path
+    }
+
+    pub fn playback_speed(&self) -> f64 {
+        self.playback_speed
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    pub fn recorder_seconds(&self) -> u32 {
+        self.recorder_seconds
+    }
+
+    pub fn ocr_backend(&self) -> OcrBackend {
+        self.ocr_backend
+    }
+
+    pub fn ocr_fixture_path(&self) -> &str {
+        &self.ocr_fixture_path
+    }
+
+    pub fn record_ocr(&self) -> bool {
+        self.record_ocr
+    }
+
+    pub fn set_source(&mut self, source: CaptureSource) {
+        self.source = source;
+    }
+
+    pub fn set_source_path(&mut self, source_path: String) {
+        self.source_path = source_path;
+    }
+
+    pub fn set_playback_speed(&mut self, playback_speed: f64) {
+        self.playback_speed = playback_speed;
+    }
+
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        self.frame_rate = frame_rate;
+    }
+
+    pub fn set_recorder_seconds(&mut self, recorder_seconds: u32) {
+        self.recorder_seconds = recorder_seconds;
+    }
+
+    pub fn set_ocr_backend(&mut self, ocr_backend: OcrBackend) {
+        self.ocr_backend = ocr_backend;
+    }
+
+    pub fn set_ocr_fixture_path(&mut self, ocr_fixture_path: String) {
+        self.ocr_fixture_path = ocr_fixture_path;
+    }
+
+    pub fn set_record_ocr(&mut self, record_ocr: bool) {
+        self.record_ocr = record_ocr;
+    }
 }